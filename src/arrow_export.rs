@@ -0,0 +1,335 @@
+use std::sync::Arc;
+
+use arrow::array::{
+    ArrayRef, BinaryBuilder, BooleanBuilder, Date32Builder, Decimal256Array, Float64Builder,
+    Int16Builder, Int32Builder, Int64Builder, StringArray, StringBuilder, Time64MicrosecondBuilder,
+    TimestampMicrosecondBuilder, UInt32Builder, UInt64Builder,
+};
+use arrow::datatypes::{i256, Field, Schema, DECIMAL256_MAX_PRECISION};
+use arrow::error::ArrowError;
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use serde_json::Value;
+use sqlx::any::{Any, AnyRow};
+use sqlx::error::BoxDynError;
+use sqlx::types::BigDecimal;
+use sqlx::{Column, Decode, Describe, Row, TypeInfo, ValueRef};
+
+use crate::sql_to_json::{column_kind, decode_decimal, DecodeError, DecodeWarning, ValueKind};
+
+/// Days from 0001-01-01 to 1970-01-01, for converting chrono dates to Arrow `Date32`.
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
+/// Builds one record batch from indexed rows, decoding each column straight into a typed
+/// Arrow array. Column types follow [`column_kind`], so integers keep their width and
+/// timestamps stay timestamps; decimals become `Decimal256`. Every field is nullable, since
+/// sqlx does not report nullability for arbitrary queries. Cells that fail to decode are left
+/// null and added to `warnings`. An empty result takes its schema from `describe`.
+pub fn rows_to_batch(
+    rows: &[AnyRow],
+    describe: Option<&Describe<Any>>,
+    warnings: &mut Vec<DecodeWarning>,
+) -> Result<RecordBatch, ArrowError> {
+    let columns = match rows.first() {
+        Some(first) => first.columns(),
+        None => describe.map(|d| d.columns()).unwrap_or_default(),
+    };
+    if columns.is_empty() {
+        return Ok(RecordBatch::new_empty(Arc::new(Schema::empty())));
+    }
+
+    let mut fields = Vec::new();
+    let mut arrays = Vec::new();
+    for col in columns {
+        let kind = column_kind(rows, col);
+        let mut builder = ColumnBuilder::new(kind, rows.len());
+        for (index, row) in rows.iter().enumerate() {
+            if let Err(e) = builder.append(row, col.ordinal()) {
+                builder.append_null();
+                let type_name = match row.try_get_raw(col.ordinal()) {
+                    Ok(value) => value.type_info().name().to_string(),
+                    Err(_) => col.type_info().name().to_string(),
+                };
+                warnings.push(DecodeWarning {
+                    row: index,
+                    error: DecodeError {
+                        column: col.name().to_string(),
+                        type_name,
+                        message: e.to_string(),
+                    },
+                });
+            }
+        }
+        let array = builder.finish()?;
+        fields.push(Field::new(col.name(), array.data_type().clone(), true));
+        arrays.push(array);
+    }
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)
+}
+
+/// Serializes a batch in the Arrow IPC streaming format.
+pub fn to_ipc(batch: &RecordBatch) -> Result<Vec<u8>, ArrowError> {
+    let mut writer = StreamWriter::try_new(Vec::new(), &batch.schema())?;
+    writer.write(batch)?;
+    writer.into_inner()
+}
+
+/// Serializes a batch as a Snappy-compressed Parquet file.
+pub fn to_parquet(batch: &RecordBatch) -> Result<Vec<u8>, ParquetError> {
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(Vec::new(), batch.schema(), Some(props))?;
+    writer.write(batch)?;
+    writer.into_inner()
+}
+
+enum ColumnBuilder {
+    Float(Float64Builder),
+    /// Collected first, since the column's scale is only known once every value is in.
+    Decimal(Vec<Option<BigDecimal>>),
+    BigInt(Int64Builder),
+    Int(Int32Builder),
+    SmallInt(Int16Builder),
+    BigUnsigned(UInt64Builder),
+    Unsigned(UInt32Builder),
+    Bool(BooleanBuilder),
+    Date(Date32Builder),
+    Time(Time64MicrosecondBuilder),
+    Timestamp(TimestampMicrosecondBuilder),
+    DateTime(TimestampMicrosecondBuilder),
+    Json(StringBuilder),
+    Binary(BinaryBuilder),
+    Text(StringBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(kind: ValueKind, capacity: usize) -> Self {
+        match kind {
+            ValueKind::Float => Self::Float(Float64Builder::with_capacity(capacity)),
+            ValueKind::Decimal => Self::Decimal(Vec::with_capacity(capacity)),
+            ValueKind::BigInt => Self::BigInt(Int64Builder::with_capacity(capacity)),
+            ValueKind::Int => Self::Int(Int32Builder::with_capacity(capacity)),
+            ValueKind::SmallInt => Self::SmallInt(Int16Builder::with_capacity(capacity)),
+            ValueKind::BigUnsigned => Self::BigUnsigned(UInt64Builder::with_capacity(capacity)),
+            ValueKind::Unsigned => Self::Unsigned(UInt32Builder::with_capacity(capacity)),
+            ValueKind::Bool => Self::Bool(BooleanBuilder::with_capacity(capacity)),
+            ValueKind::Date => Self::Date(Date32Builder::with_capacity(capacity)),
+            ValueKind::Time => Self::Time(Time64MicrosecondBuilder::with_capacity(capacity)),
+            ValueKind::Timestamp => Self::Timestamp(
+                TimestampMicrosecondBuilder::with_capacity(capacity).with_timezone("UTC"),
+            ),
+            ValueKind::DateTime => {
+                Self::DateTime(TimestampMicrosecondBuilder::with_capacity(capacity))
+            }
+            ValueKind::Json => Self::Json(StringBuilder::with_capacity(capacity, capacity * 16)),
+            ValueKind::Binary => {
+                Self::Binary(BinaryBuilder::with_capacity(capacity, capacity * 32))
+            }
+            // Postgres arrays, intervals, UUIDs and network addresses keep their text form.
+            ValueKind::Uuid
+            | ValueKind::Interval
+            | ValueKind::Network
+            | ValueKind::Array
+            | ValueKind::Text => Self::Text(StringBuilder::with_capacity(capacity, capacity * 16)),
+        }
+    }
+
+    /// Appends one cell. On error nothing has been appended.
+    fn append(&mut self, row: &AnyRow, ordinal: usize) -> Result<(), BoxDynError> {
+        let value = row.try_get_raw(ordinal)?;
+        if value.is_null() {
+            self.append_null();
+            return Ok(());
+        }
+        match self {
+            Self::Float(b) => b.append_value(f64::decode(value)?),
+            Self::Decimal(values) => {
+                values.push(decode_decimal(value, || row.try_get_raw(ordinal).unwrap())?)
+            }
+            Self::BigInt(b) => b.append_value(i64::decode(value)?),
+            Self::Int(b) => b.append_value(i32::decode(value)?),
+            Self::SmallInt(b) => b.append_value(i16::decode(value)?),
+            Self::BigUnsigned(b) => b.append_value(u64::decode(value)?),
+            Self::Unsigned(b) => b.append_value(u32::decode(value)?),
+            Self::Bool(b) => b.append_value(bool::decode(value)?),
+            Self::Date(b) => b.append_value(
+                NaiveDate::decode(value)?.num_days_from_ce() - UNIX_EPOCH_DAYS_FROM_CE,
+            ),
+            Self::Time(b) => {
+                let time = NaiveTime::decode(value)?;
+                b.append_value(
+                    i64::from(time.num_seconds_from_midnight()) * 1_000_000
+                        + i64::from(time.nanosecond() / 1_000),
+                )
+            }
+            Self::Timestamp(b) => {
+                b.append_value(DateTime::<FixedOffset>::decode(value)?.timestamp_micros())
+            }
+            Self::DateTime(b) => {
+                b.append_value(NaiveDateTime::decode(value)?.and_utc().timestamp_micros())
+            }
+            Self::Json(b) => b.append_value(Value::decode(value)?.to_string()),
+            Self::Binary(b) => b.append_value(Vec::<u8>::decode(value)?),
+            Self::Text(b) => b.append_value(String::decode(value)?),
+        }
+        Ok(())
+    }
+
+    fn append_null(&mut self) {
+        match self {
+            Self::Float(b) => b.append_null(),
+            Self::Decimal(values) => values.push(None),
+            Self::BigInt(b) => b.append_null(),
+            Self::Int(b) => b.append_null(),
+            Self::SmallInt(b) => b.append_null(),
+            Self::BigUnsigned(b) => b.append_null(),
+            Self::Unsigned(b) => b.append_null(),
+            Self::Bool(b) => b.append_null(),
+            Self::Date(b) => b.append_null(),
+            Self::Time(b) => b.append_null(),
+            Self::Timestamp(b) | Self::DateTime(b) => b.append_null(),
+            Self::Json(b) | Self::Text(b) => b.append_null(),
+            Self::Binary(b) => b.append_null(),
+        }
+    }
+
+    fn finish(self) -> Result<ArrayRef, ArrowError> {
+        Ok(match self {
+            Self::Float(mut b) => Arc::new(b.finish()),
+            Self::Decimal(values) => decimal_array(&values)?,
+            Self::BigInt(mut b) => Arc::new(b.finish()),
+            Self::Int(mut b) => Arc::new(b.finish()),
+            Self::SmallInt(mut b) => Arc::new(b.finish()),
+            Self::BigUnsigned(mut b) => Arc::new(b.finish()),
+            Self::Unsigned(mut b) => Arc::new(b.finish()),
+            Self::Bool(mut b) => Arc::new(b.finish()),
+            Self::Date(mut b) => Arc::new(b.finish()),
+            Self::Time(mut b) => Arc::new(b.finish()),
+            Self::Timestamp(mut b) | Self::DateTime(mut b) => Arc::new(b.finish()),
+            Self::Json(mut b) | Self::Text(mut b) => Arc::new(b.finish()),
+            Self::Binary(mut b) => Arc::new(b.finish()),
+        })
+    }
+}
+
+/// A `Decimal256` column with one scale for all values, the largest any of them has. Values
+/// with more digits, or more fractional digits, than `Decimal256` holds turn the whole column
+/// into exact strings instead.
+fn decimal_array(values: &[Option<BigDecimal>]) -> Result<ArrayRef, ArrowError> {
+    let max_digits = i64::from(DECIMAL256_MAX_PRECISION);
+    let scale = values
+        .iter()
+        .flatten()
+        .map(|v| v.as_bigint_and_exponent().1)
+        .max()
+        .unwrap_or(0)
+        .max(0);
+    let to_unscaled = |value: &Option<BigDecimal>| match value {
+        None => Some(None),
+        Some(value) => {
+            let digits = value
+                .with_scale(scale)
+                .as_bigint_and_exponent()
+                .0
+                .to_string();
+            if digits.trim_start_matches('-').len() as i64 > max_digits {
+                return None;
+            }
+            i256::from_string(&digits).map(Some)
+        }
+    };
+    let unscaled: Option<Vec<Option<i256>>> = if scale > max_digits {
+        None
+    } else {
+        values.iter().map(to_unscaled).collect()
+    };
+
+    Ok(match unscaled {
+        Some(unscaled) => Arc::new(
+            Decimal256Array::from(unscaled)
+                .with_precision_and_scale(DECIMAL256_MAX_PRECISION, scale as i8)?,
+        ),
+        None => Arc::new(StringArray::from_iter(
+            values.iter().map(|v| v.as_ref().map(|v| v.to_string())),
+        )),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_to_json::test_database_url;
+    use arrow::array::Array;
+    use arrow::datatypes::DataType;
+    use arrow::ipc::reader::StreamReader;
+    use sqlx::{Connection, Executor};
+
+    #[tokio::test]
+    async fn test_rows_to_batch() -> anyhow::Result<()> {
+        let mut c = sqlx::AnyConnection::connect(&test_database_url()).await?;
+        let rows = sqlx::query(
+            "SELECT 1 as id, 'a' as name, NULL as missing \
+             UNION ALL SELECT 2, NULL, NULL",
+        )
+        .fetch_all(&mut c)
+        .await?;
+
+        let mut warnings = Vec::new();
+        let batch = rows_to_batch(&rows, None, &mut warnings)?;
+        assert!(warnings.is_empty());
+        assert_eq!(batch.num_rows(), 2);
+        let schema = batch.schema();
+        let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(names, ["id", "name", "missing"]);
+        assert_eq!(schema.field(1).data_type(), &DataType::Utf8);
+        assert_eq!(batch.column(1).null_count(), 1);
+        assert_eq!(batch.column(2).null_count(), 2);
+
+        let ipc = to_ipc(&batch)?;
+        let read: Vec<RecordBatch> =
+            StreamReader::try_new(ipc.as_slice(), None)?.collect::<Result<_, _>>()?;
+        assert_eq!(read, [batch.clone()]);
+
+        assert!(to_parquet(&batch)?.starts_with(b"PAR1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_decimal_array() {
+        let decimals = |values: &[&str]| -> Vec<Option<BigDecimal>> {
+            values.iter().map(|v| v.parse().ok()).collect()
+        };
+        let array = decimal_array(&decimals(&["1.5", "-20", "0.125"])).unwrap();
+        assert_eq!(array.data_type(), &DataType::Decimal256(76, 3));
+
+        // A scale beyond Decimal256 would round, so the column falls back to exact strings.
+        let tiny = format!("0.{}1", "0".repeat(80));
+        let array = decimal_array(&decimals(&["1.5", &tiny])).unwrap();
+        let strings = array.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(strings.value(1).parse::<BigDecimal>().ok(), tiny.parse().ok());
+    }
+
+    #[tokio::test]
+    async fn test_empty_rows_keep_schema() -> anyhow::Result<()> {
+        let mut c = sqlx::AnyConnection::connect(&test_database_url()).await?;
+        let describe = (&mut c)
+            .describe("SELECT 1 AS id, 'a' AS name WHERE 1 = 0")
+            .await?;
+        let batch = rows_to_batch(&[], Some(&describe), &mut Vec::new())?;
+        assert_eq!(batch.num_rows(), 0);
+        let schema = batch.schema();
+        let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(names, ["id", "name"]);
+
+        let ipc = to_ipc(&batch)?;
+        let read = StreamReader::try_new(ipc.as_slice(), None)?;
+        assert_eq!(read.schema(), schema);
+        Ok(())
+    }
+}
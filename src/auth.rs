@@ -0,0 +1,270 @@
+use std::time::Duration;
+
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome},
+    response::{content::RawJson, status},
+    Request,
+};
+use serde_json::{json, Map};
+use sqlx::any::AnyPool;
+use sqlx::Row;
+
+use crate::chains::ChainRegistry;
+use crate::config::ServerConfig;
+use crate::params::{self, QueryParams};
+use crate::query::QueryEngine;
+use crate::utils::{json_response, sha256_hex};
+
+/// Keys are stored as the hex SHA-256 of the key, never in the clear. Columns other than
+/// `key_hash` and `name` are optional restrictions; NULL means unrestricted. `engines` and
+/// `chains` are comma-separated lists. A key is revoked by deleting its row. The table lives in
+/// the database indexed queries read, so the SQL guard denies it by name.
+///
+/// To add a key on Postgres:
+/// `INSERT INTO api_keys (key_hash, name, engines, max_rows)
+///  VALUES (encode(sha256('<key>'), 'hex'), 'dashboard', 'indexed', 1000)`.
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS api_keys (
+    key_hash VARCHAR(64) PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    engines VARCHAR(255),
+    chains TEXT,
+    max_rows BIGINT,
+    max_timeout_ms BIGINT
+)";
+
+const LOOKUP: &str = "SELECT name, engines, chains, max_rows, max_timeout_ms \
+                      FROM api_keys WHERE key_hash = $key_hash";
+
+/// Creates the `api_keys` table when it does not exist yet.
+pub async fn ensure_table(pool: &AnyPool) -> Result<(), sqlx::Error> {
+    sqlx::query(CREATE_TABLE).execute(pool).await.map(|_| ())
+}
+
+/// The form a key is stored in: lowercase hex SHA-256.
+pub fn hash_key(key: &str) -> String {
+    sha256_hex(key.as_bytes())
+}
+
+/// What the caller's key may do. The default allows everything and is what every request
+/// gets when keys are not required.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyPolicy {
+    /// The key's name, for logs.
+    pub name: Option<String>,
+    /// `rpc`, `indexed`, or single engines (`eql`, `suiql`, `sql`).
+    pub engines: Option<Vec<String>>,
+    /// Canonical chain names.
+    pub chains: Option<Vec<String>>,
+    pub max_rows: Option<usize>,
+    pub max_timeout: Option<Duration>,
+}
+
+impl KeyPolicy {
+    pub fn allows_engine(&self, engine: QueryEngine) -> bool {
+        let query_type = match engine {
+            QueryEngine::Sql => "indexed",
+            QueryEngine::Rpc(_) => "rpc",
+        };
+        self.engines.as_ref().map_or(true, |engines| {
+            engines
+                .iter()
+                .any(|allowed| allowed == engine.name() || allowed == query_type)
+        })
+    }
+
+    /// The first of `chains` (canonical names) the key may not query.
+    pub fn denied_chain<'a>(&self, chains: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+        let allowed = self.chains.as_ref()?;
+        chains
+            .into_iter()
+            .find(|chain| !allowed.iter().any(|allowed| allowed == chain))
+    }
+
+    /// The row cap for an indexed query: the server's, lowered by the key's.
+    pub fn row_limit(&self, server_max: usize) -> usize {
+        self.max_rows.map_or(server_max, |max| max.min(server_max))
+    }
+
+    pub fn cap_timeout(&self, timeout: Duration) -> Duration {
+        self.max_timeout.map_or(timeout, |max| max.min(timeout))
+    }
+}
+
+/// The name a chain goes by in policies and checks: the registry's canonical name, or the
+/// lowercased name itself for chains the registry does not know.
+pub fn canonical_chain(chains: &ChainRegistry, name: &str) -> String {
+    chains
+        .get(name)
+        .map_or_else(|| name.to_lowercase(), |chain| chain.name.clone())
+}
+
+#[derive(Debug, Clone)]
+pub enum AuthError {
+    Missing,
+    Invalid,
+    Lookup(String),
+}
+
+impl AuthError {
+    fn status(&self) -> Status {
+        match self {
+            AuthError::Missing | AuthError::Invalid => Status::Unauthorized,
+            AuthError::Lookup(_) => Status::InternalServerError,
+        }
+    }
+
+    pub fn response(&self) -> status::Custom<RawJson<String>> {
+        match self {
+            AuthError::Missing => json_response(
+                Status::Unauthorized,
+                json!({
+                    "error": "An API key is required. Send it as `Authorization: Bearer <key>` \
+                              or in the X-API-Key header.",
+                    "code": "missing_api_key",
+                }),
+            ),
+            AuthError::Invalid => json_response(
+                Status::Unauthorized,
+                json!({ "error": "The API key is not valid.", "code": "invalid_api_key" }),
+            ),
+            AuthError::Lookup(e) => json_response(
+                Status::InternalServerError,
+                json!({ "error": format!("Could not check the API key: {e}") }),
+            ),
+        }
+    }
+}
+
+/// Request guard for the query routes. Resolves to the caller's [`KeyPolicy`], or to an
+/// unrestricted one when `REQUIRE_API_KEY` is off. The key is looked up once per request, however
+/// many guards ask for it.
+pub struct ApiKey(pub KeyPolicy);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiKey {
+    type Error = AuthError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.local_cache_async(authenticate(request)).await {
+            Ok(policy) => Outcome::Success(ApiKey(policy.clone())),
+            Err(e) => Outcome::Error((e.status(), e.clone())),
+        }
+    }
+}
+
+async fn authenticate(request: &Request<'_>) -> Result<KeyPolicy, AuthError> {
+    let rocket = request.rocket();
+    let (Some(config), Some(pool)) = (rocket.state::<ServerConfig>(), rocket.state::<AnyPool>())
+    else {
+        return Err(AuthError::Lookup(
+            "server state is not configured".to_string(),
+        ));
+    };
+    if !config.require_api_key {
+        return Ok(KeyPolicy::default());
+    }
+    let key = presented_key(request).ok_or(AuthError::Missing)?;
+    match lookup(pool, &config.chains, key).await {
+        Ok(Some(policy)) => Ok(policy),
+        Ok(None) => Err(AuthError::Invalid),
+        Err(e) => {
+            log::error!("API key lookup failed: {e}");
+            Err(AuthError::Lookup(e.to_string()))
+        }
+    }
+}
+
+/// The key sent as `Authorization: Bearer <key>` or in `X-API-Key`.
+pub fn presented_key<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+    let headers = request.headers();
+    headers
+        .get_one("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| headers.get_one("X-API-Key"))
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+async fn lookup(
+    pool: &AnyPool,
+    chains: &ChainRegistry,
+    key: &str,
+) -> Result<Option<KeyPolicy>, sqlx::Error> {
+    let mut params = Map::new();
+    params.insert("key_hash".to_string(), hash_key(key).into());
+    let bound = params::bind_params(LOOKUP, &QueryParams::Named(params), pool.any_kind().into())
+        .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+    let Some(row) = bound.query().fetch_optional(pool).await? else {
+        return Ok(None);
+    };
+
+    let list = |column: &str| -> Result<Option<Vec<String>>, sqlx::Error> {
+        Ok(row.try_get::<Option<String>, _>(column)?.map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_lowercase)
+                .collect()
+        }))
+    };
+    let limit = |column: &str| -> Result<Option<u64>, sqlx::Error> {
+        Ok(row
+            .try_get::<Option<i64>, _>(column)?
+            .map(|value| value.max(0) as u64))
+    };
+    Ok(Some(KeyPolicy {
+        name: Some(row.try_get("name")?),
+        engines: list("engines")?,
+        chains: list("chains")?.map(|names| {
+            names
+                .iter()
+                .map(|name| canonical_chain(chains, name))
+                .collect()
+        }),
+        max_rows: limit("max_rows")?.map(|rows| rows as usize),
+        max_timeout: limit("max_timeout_ms")?.map(Duration::from_millis),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chains::Engine;
+
+    #[test]
+    fn test_hash_key() {
+        assert_eq!(
+            hash_key("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_key_policy() {
+        let policy = KeyPolicy {
+            name: Some("dashboard".to_string()),
+            engines: Some(vec!["indexed".to_string(), "suiql".to_string()]),
+            chains: Some(vec!["eth".to_string()]),
+            max_rows: Some(100),
+            max_timeout: Some(Duration::from_secs(5)),
+        };
+        assert!(policy.allows_engine(QueryEngine::Sql));
+        assert!(policy.allows_engine(QueryEngine::Rpc(Engine::SuiQl)));
+        assert!(!policy.allows_engine(QueryEngine::Rpc(Engine::Eql)));
+        assert_eq!(policy.denied_chain(["eth", "base"]), Some("base"));
+        assert_eq!(policy.denied_chain(["eth"]), None);
+        assert_eq!(policy.row_limit(10_000), 100);
+        assert_eq!(policy.row_limit(50), 50);
+        assert_eq!(
+            policy.cap_timeout(Duration::from_secs(30)),
+            Duration::from_secs(5)
+        );
+
+        let open = KeyPolicy::default();
+        assert!(open.allows_engine(QueryEngine::Rpc(Engine::Eql)));
+        assert_eq!(open.denied_chain(["anything"]), None);
+        assert_eq!(open.row_limit(10_000), 10_000);
+    }
+}
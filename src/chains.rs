@@ -0,0 +1,468 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// The registry used when `CHAIN_REGISTRY` is unset.
+const DEFAULT_REGISTRY: &str = include_str!("../chains.json");
+
+/// Block tags that follow the head of the chain.
+const MOVING_TAGS: &[&str] = &["latest", "pending", "safe", "finalized"];
+
+/// Words that are followed by a block or checkpoint number, or a `from:to` range of them.
+const BLOCK_KEYWORDS: &[&str] = &["block", "blocks", "checkpoint", "checkpoints"];
+
+/// The interpreter that serves RPC queries for a chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    Eql,
+    SuiQl,
+}
+
+impl Engine {
+    pub fn name(self) -> &'static str {
+        match self {
+            Engine::Eql => "eql",
+            Engine::SuiQl => "suiql",
+        }
+    }
+}
+
+/// One entry of the chain registry.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Chain {
+    /// Canonical name, e.g. `eth`. Matched case-insensitively, like the aliases.
+    pub name: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub engine: Engine,
+    /// Prepended to table names in indexed queries, so `eth.blocks` reads `eth_blocks`.
+    /// Defaults to the name followed by an underscore, or to nothing when `schema` is set.
+    #[serde(default)]
+    pub table_prefix: Option<String>,
+    /// Postgres schema holding the chain's indexed tables, so `eth.blocks` can stay
+    /// `eth.blocks`. Tables are unqualified when unset.
+    #[serde(default)]
+    pub schema: Option<String>,
+    /// How long RPC results that depend on the chain head are cached, in seconds; about a
+    /// block time. Defaults to `RPC_CACHE_LATEST_TTL_SECS`.
+    #[serde(default)]
+    pub latest_ttl_secs: Option<u64>,
+    /// A block or checkpoint number known to be final. RPC results for numbers at or below it
+    /// never change and are cached until evicted; later blocks may not exist yet or may still
+    /// be reorganized away. Leave it unset on chains whose history gets reset, like devnets.
+    #[serde(default)]
+    pub finalized_height: Option<u64>,
+}
+
+impl Chain {
+    /// The schema and table name that `<chain>.<table>` refers to.
+    pub fn indexed_table(&self, table: &str) -> (Option<&str>, String) {
+        let table = match (&self.table_prefix, &self.schema) {
+            (Some(prefix), _) => format!("{prefix}{table}"),
+            (None, Some(_)) => table.to_string(),
+            (None, None) => format!("{}_{table}", self.name),
+        };
+        (self.schema.as_deref(), table)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegistryFile {
+    chains: Vec<Chain>,
+}
+
+/// The chains the server knows, used both to route RPC queries to an interpreter and to map
+/// `<chain>.<table>` references in indexed queries to tables (see
+/// [`map_chain_tables`](crate::sql_rewrite::map_chain_tables)). Loaded once at startup from the
+/// JSON file named by `CHAIN_REGISTRY`, or from the bundled `chains.json`.
+#[derive(Debug, Clone)]
+pub struct ChainRegistry {
+    chains: Vec<Chain>,
+    /// Lowercase names and aliases, to the chain's index in `chains`.
+    lookup: HashMap<String, usize>,
+}
+
+impl Default for ChainRegistry {
+    fn default() -> Self {
+        Self::from_json(DEFAULT_REGISTRY).expect("the bundled chains.json is valid")
+    }
+}
+
+impl ChainRegistry {
+    pub fn from_env() -> Self {
+        match std::env::var("CHAIN_REGISTRY") {
+            Ok(path) if !path.trim().is_empty() => Self::load(path.trim())
+                .unwrap_or_else(|e| panic!("Could not load chain registry {path}: {e}")),
+            _ => Self::default(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::from_json(&text)
+    }
+
+    /// Parses a registry, rejecting names or aliases that are used twice.
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let file: RegistryFile = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        let mut lookup = HashMap::new();
+        for (index, chain) in file.chains.iter().enumerate() {
+            for name in std::iter::once(&chain.name).chain(&chain.aliases) {
+                let key = name.trim().to_lowercase();
+                if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    return Err(format!("invalid chain name {name:?}"));
+                }
+                if lookup.insert(key, index).is_some() {
+                    return Err(format!("chain name {name:?} is used more than once"));
+                }
+            }
+        }
+        Ok(Self {
+            chains: file.chains,
+            lookup,
+        })
+    }
+
+    /// The chain whose indexed tables include `[schema.]table`, going by schemas and table
+    /// prefixes. Chains without a schema match in any schema, so `public.eth_blocks` still
+    /// counts as `eth`. The longest matching prefix wins.
+    pub fn chain_for_table(&self, schema: Option<&str>, table: &str) -> Option<&Chain> {
+        let table = table.to_lowercase();
+        self.chains
+            .iter()
+            .filter_map(|chain| {
+                let prefix = chain.indexed_table("").1.to_lowercase();
+                let schema_matches = match (&chain.schema, schema) {
+                    (Some(expected), Some(schema)) => expected.eq_ignore_ascii_case(schema),
+                    (Some(_), None) => false,
+                    (None, _) => true,
+                };
+                (schema_matches && table.starts_with(&prefix)).then_some((prefix.len(), chain))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, chain)| chain)
+    }
+
+    /// Finds a chain by name or alias, ignoring case.
+    pub fn get(&self, name: &str) -> Option<&Chain> {
+        self.lookup
+            .get(&name.to_lowercase())
+            .map(|&index| &self.chains[index])
+    }
+
+    /// The engine for an RPC query, from the chains it targets (see [`target_chains`]). EQL
+    /// when it names no registered chain; an error when its chains are served by different
+    /// engines.
+    pub fn rpc_engine(&self, query: &str) -> Result<Engine, String> {
+        let mut engine = None;
+        for name in target_chains(query) {
+            let Some(chain) = self.get(name) else {
+                continue;
+            };
+            match engine {
+                Some((first, first_chain)) if first != chain.engine => {
+                    return Err(format!(
+                        "The query targets {first_chain} and {name}, which are served by \
+                         different engines."
+                    ))
+                }
+                Some(_) => {}
+                None => engine = Some((chain.engine, name)),
+            }
+        }
+        Ok(engine.map_or(Engine::Eql, |(engine, _)| engine))
+    }
+
+    /// How long the result of an RPC query that follows the chain head may be cached: the
+    /// shortest `latest_ttl_secs` among the chains it targets, with `default` for chains that
+    /// set none and for queries that name no registered chain.
+    pub fn latest_ttl(&self, query: &str, default: Duration) -> Duration {
+        target_chains(query)
+            .into_iter()
+            .filter_map(|name| self.get(name))
+            .map(|chain| chain.latest_ttl_secs.map_or(default, Duration::from_secs))
+            .min()
+            .unwrap_or(default)
+    }
+
+    /// Whether an EQL or SuiQL program only reads final history, so its result never changes:
+    /// every statement (each starts with `GET`) names a block or checkpoint by hash, or by
+    /// number or `from:to` range at or below the `finalized_height` of every chain it targets,
+    /// and none mentions a tag such as `latest`. Anything else, including account and object
+    /// state without a block, is taken to follow the chain head.
+    pub fn is_pinned(&self, query: &str) -> bool {
+        let finalized = target_chains(query)
+            .into_iter()
+            .map(|name| self.get(name).and_then(|chain| chain.finalized_height))
+            .min()
+            .flatten();
+        let Some(finalized) = finalized else {
+            return false;
+        };
+        let tokens = tokenize(query);
+        let mut statements = tokens
+            .split(|token| matches!(token, Token::Word(word) if word.eq_ignore_ascii_case("get")))
+            .filter(|statement| !statement.is_empty())
+            .peekable();
+        statements.peek().is_some()
+            && statements.all(|statement| statement_is_pinned(statement, finalized))
+    }
+}
+
+/// The chain names following `ON` in an EQL or SuiQL program, as in
+/// `GET balance FROM account 0x… ON eth, base`. Words inside string literals are skipped, so an
+/// address or a label that happens to contain a chain name does not count.
+pub fn target_chains(query: &str) -> Vec<&str> {
+    let tokens = tokenize(query);
+    let mut chains = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let on = matches!(tokens[i], Token::Word(word) if word.eq_ignore_ascii_case("on"));
+        i += 1;
+        if !on {
+            continue;
+        }
+        while let Some(Token::Word(chain)) = tokens.get(i) {
+            chains.push(*chain);
+            i += 1;
+            if tokens.get(i) != Some(&Token::Comma) {
+                break;
+            }
+            i += 1;
+        }
+    }
+    chains
+}
+
+fn statement_is_pinned(tokens: &[Token], finalized: u64) -> bool {
+    let is_number = |token: Option<&Token>| match token {
+        Some(Token::Word(word)) => match word.strip_prefix("0x") {
+            Some(hex) => !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()),
+            None => word.chars().all(|c| c.is_ascii_digit()),
+        },
+        _ => false,
+    };
+    // A 32-byte hash names one block for good; a number only once it is final.
+    let is_final = |token: Option<&Token>| match token {
+        Some(Token::Word(word)) => match word.strip_prefix("0x") {
+            Some(hex) if hex.len() == 64 => hex.chars().all(|c| c.is_ascii_hexdigit()),
+            Some(hex) => u64::from_str_radix(hex, 16).is_ok_and(|n| n <= finalized),
+            None => word.parse::<u64>().is_ok_and(|n| n <= finalized),
+        },
+        _ => false,
+    };
+    let mut pinned = false;
+    for (i, token) in tokens.iter().enumerate() {
+        let Token::Word(word) = token else {
+            continue;
+        };
+        let is = |words: &[&str]| words.iter().any(|w| word.eq_ignore_ascii_case(w));
+        if is(MOVING_TAGS) {
+            return false;
+        }
+        if !is(BLOCK_KEYWORDS) || !is_number(tokens.get(i + 1)) {
+            continue;
+        }
+        // An open range such as `block 100:` runs to the head.
+        let ranged = tokens.get(i + 2) == Some(&Token::Colon);
+        if !is_final(tokens.get(i + 1)) || (ranged && !is_final(tokens.get(i + 3))) {
+            return false;
+        }
+        pinned = true;
+    }
+    pinned
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token<'a> {
+    Word(&'a str),
+    Comma,
+    Colon,
+    /// A string literal or any other punctuation.
+    Other,
+}
+
+fn tokenize(query: &str) -> Vec<Token<'_>> {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut tokens = Vec::new();
+    let mut rest = query.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if is_word(c) {
+            let len = rest.find(|c| !is_word(c)).unwrap_or(rest.len());
+            tokens.push(Token::Word(&rest[..len]));
+            len
+        } else if c == '\'' || c == '"' {
+            // An unterminated literal runs to the end of the query.
+            tokens.push(Token::Other);
+            rest[1..].find(c).map_or(rest.len(), |i| i + 2)
+        } else {
+            tokens.push(match c {
+                ',' => Token::Comma,
+                ':' => Token::Colon,
+                _ => Token::Other,
+            });
+            c.len_utf8()
+        };
+        rest = rest[len..].trim_start();
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_registry() {
+        let registry = ChainRegistry::default();
+        assert_eq!(registry.get("ETH").unwrap().engine, Engine::Eql);
+        assert_eq!(
+            registry.rpc_engine("GET * FROM account 0x1 ON suitest"),
+            Ok(Engine::SuiQl)
+        );
+        assert_eq!(
+            registry.rpc_engine("GET * FROM account 0x1 ON eth"),
+            Ok(Engine::Eql)
+        );
+        assert_eq!(
+            registry.get("eth").unwrap().indexed_table("blocks"),
+            (None, "eth_blocks".to_string())
+        );
+    }
+
+    #[test]
+    fn test_target_chains() {
+        assert_eq!(
+            target_chains(
+                "GET balance FROM account 0x5ui ON eth, base\nGET nonce FROM account 0x1 on arb"
+            ),
+            ["eth", "base", "arb"]
+        );
+        assert_eq!(
+            target_chains("GET * FROM account 'sui' ON eth WHERE label = \"on sui\""),
+            ["eth"]
+        );
+        assert!(target_chains("GET * FROM block 1").is_empty());
+
+        let registry = ChainRegistry::default();
+        assert_eq!(
+            registry.rpc_engine("GET * FROM tx 0x1 ON eth WHERE memo = 'SUI'"),
+            Ok(Engine::Eql)
+        );
+        assert_eq!(
+            registry.rpc_engine("GET * FROM object 0x2 ON unknown, sui"),
+            Ok(Engine::SuiQl)
+        );
+        assert!(registry
+            .rpc_engine("GET * FROM account 0x1 ON eth, sui")
+            .is_err());
+    }
+
+    #[test]
+    fn test_is_pinned() {
+        let registry = ChainRegistry::from_json(
+            r#"{"chains": [
+                {"name": "eth", "engine": "eql", "finalized_height": 5000000},
+                {"name": "sui", "engine": "suiql", "finalized_height": 1000},
+                {"name": "base", "engine": "eql"}
+            ]}"#,
+        )
+        .unwrap();
+        let is_pinned = |query: &str| registry.is_pinned(query);
+        assert!(is_pinned("GET number, hash FROM block 100 ON eth"));
+        assert!(is_pinned(
+            "GET * FROM block 100:200 ON eth\nget * from checkpoint 0x1f on sui"
+        ));
+        assert!(is_pinned(
+            "GET * FROM log WHERE block 4638657:4638758, address 0x1 ON eth"
+        ));
+        assert!(!is_pinned("GET * FROM block latest ON eth"));
+        assert!(!is_pinned("GET * FROM block 100:latest ON eth"));
+        assert!(!is_pinned("GET * FROM block 100: ON eth"));
+        assert!(!is_pinned("GET balance FROM account 0x1 ON eth"));
+        assert!(!is_pinned(
+            "GET * FROM block 1 ON eth\nGET balance FROM account 0x1 ON eth"
+        ));
+        assert!(is_pinned(
+            "GET * FROM block 1 ON eth WHERE label = 'latest'"
+        ));
+        assert!(!is_pinned(""));
+
+        // Blocks past the finalized height may not exist yet, or may still change.
+        assert!(!is_pinned("GET * FROM block 5000001 ON eth"));
+        assert!(!is_pinned("GET * FROM block 4999999:5000001 ON eth"));
+        assert!(!is_pinned("GET * FROM checkpoint 0x3e9 ON sui"));
+        assert!(!is_pinned("GET * FROM block 2000 ON eth, sui"));
+        let hash = format!("0x{}", "ab".repeat(32));
+        assert!(is_pinned(&format!("GET * FROM block {hash} ON eth")));
+        // Chains without a finalized height, or none named, are never pinned.
+        assert!(!is_pinned("GET * FROM block 1 ON base"));
+        assert!(!is_pinned("GET * FROM block 1 ON unknown"));
+        assert!(!is_pinned("GET * FROM block 1"));
+
+        let registry = ChainRegistry::from_json(
+            r#"{"chains": [
+                {"name": "eth", "engine": "eql", "latest_ttl_secs": 12},
+                {"name": "base", "engine": "eql"}
+            ]}"#,
+        )
+        .unwrap();
+        let default = Duration::from_secs(2);
+        let ttl = |query| registry.latest_ttl(query, default).as_secs();
+        assert_eq!(ttl("GET * FROM block latest ON eth"), 12);
+        assert_eq!(ttl("GET * FROM block latest ON eth, base"), 2);
+        assert_eq!(ttl("GET * FROM block latest ON unknown"), 2);
+    }
+
+    #[test]
+    fn test_registry_file() {
+        let registry = ChainRegistry::from_json(
+            r#"{"chains": [
+                {"name": "eth", "aliases": ["Ethereum"], "engine": "eql",
+                 "table_prefix": "mainnet_", "schema": "indexed"},
+                {"name": "movement", "engine": "suiql"},
+                {"name": "base", "engine": "eql", "schema": "base"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(registry.get("ETHEREUM").unwrap().name, "eth");
+        let table = |chain, table| registry.get(chain).unwrap().indexed_table(table);
+        assert_eq!(
+            table("ethereum", "logs"),
+            (Some("indexed"), "mainnet_logs".to_string())
+        );
+        assert_eq!(
+            table("movement", "events"),
+            (None, "movement_events".to_string())
+        );
+        assert_eq!(
+            table("base", "blocks"),
+            (Some("base"), "blocks".to_string())
+        );
+
+        let chain = |schema, table| registry.chain_for_table(schema, table).map(|c| &c.name[..]);
+        assert_eq!(chain(Some("indexed"), "mainnet_logs"), Some("eth"));
+        assert_eq!(chain(None, "mainnet_logs"), None);
+        assert_eq!(chain(Some("public"), "Movement_events"), Some("movement"));
+        assert_eq!(chain(Some("base"), "anything"), Some("base"));
+        assert_eq!(chain(None, "users"), None);
+        assert_eq!(
+            registry.rpc_engine("GET * FROM tx ON movement"),
+            Ok(Engine::SuiQl)
+        );
+        assert_eq!(registry.rpc_engine("GET * FROM tx ON sui"), Ok(Engine::Eql));
+
+        let duplicate = r#"{"chains": [
+            {"name": "eth", "engine": "eql"},
+            {"name": "mainnet", "aliases": ["ETH"], "engine": "eql"}
+        ]}"#;
+        assert!(ChainRegistry::from_json(duplicate).is_err());
+        assert!(
+            ChainRegistry::from_json(r#"{"chains": [{"name": "a.b", "engine": "eql"}]}"#).is_err()
+        );
+    }
+}
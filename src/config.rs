@@ -0,0 +1,139 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::chains::ChainRegistry;
+use crate::cors::CorsConfig;
+use crate::rate_limit::RateLimitConfig;
+use crate::result_cache::CacheConfig;
+use crate::sql_guard::GuardConfig;
+
+/// The shortest deadline a query can get, even when 0 is configured or requested.
+const MIN_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Server-wide settings, read once from the environment at startup.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub guard: GuardConfig,
+    /// Known chains, from the file named by `CHAIN_REGISTRY` or the bundled `chains.json`.
+    pub chains: ChainRegistry,
+    /// Used when a request does not set `timeout_ms` (`QUERY_TIMEOUT_MS`).
+    pub query_timeout: Duration,
+    /// Used for RPC queries that do not set `timeout_ms` (`RPC_TIMEOUT_MS`).
+    pub rpc_timeout: Duration,
+    /// Upper bound for a requested `timeout_ms` (`MAX_QUERY_TIMEOUT_MS`).
+    pub max_query_timeout: Duration,
+    /// Most rows an indexed query may return; longer results are truncated (`MAX_ROWS`).
+    pub max_rows: usize,
+    /// Whether query routes need an API key from the `api_keys` table (`REQUIRE_API_KEY`).
+    pub require_api_key: bool,
+    /// Per-client query rate and concurrency limits.
+    pub rate_limit: RateLimitConfig,
+    /// Origins, methods and headers browsers may use to call the API.
+    pub cors: CorsConfig,
+    /// Lifetimes and memory cap of the result cache.
+    pub cache: CacheConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            guard: GuardConfig::default(),
+            chains: ChainRegistry::default(),
+            query_timeout: Duration::from_secs(30),
+            rpc_timeout: Duration::from_secs(60),
+            max_query_timeout: Duration::from_secs(120),
+            max_rows: 10_000,
+            require_api_key: true,
+            rate_limit: RateLimitConfig::default(),
+            cors: CorsConfig::default(),
+            cache: CacheConfig::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            guard: GuardConfig::from_env(),
+            chains: ChainRegistry::from_env(),
+            query_timeout: env_millis("QUERY_TIMEOUT_MS", defaults.query_timeout),
+            rpc_timeout: env_millis("RPC_TIMEOUT_MS", defaults.rpc_timeout),
+            max_query_timeout: env_millis("MAX_QUERY_TIMEOUT_MS", defaults.max_query_timeout)
+                .max(MIN_TIMEOUT),
+            max_rows: env_parse("MAX_ROWS", defaults.max_rows),
+            require_api_key: env_parse("REQUIRE_API_KEY", defaults.require_api_key),
+            rate_limit: RateLimitConfig::from_env(),
+            cors: CorsConfig::from_env(),
+            cache: CacheConfig::from_env(),
+        }
+    }
+
+    /// The deadline for one indexed query: the requested one if any, capped at the server
+    /// maximum.
+    pub fn query_timeout(&self, requested_ms: Option<u64>) -> Duration {
+        self.clamp_timeout(requested_ms, self.query_timeout)
+    }
+
+    /// Like [`ServerConfig::query_timeout`], for the EQL and SuiQL interpreters.
+    pub fn rpc_timeout(&self, requested_ms: Option<u64>) -> Duration {
+        self.clamp_timeout(requested_ms, self.rpc_timeout)
+    }
+
+    fn clamp_timeout(&self, requested_ms: Option<u64>, default: Duration) -> Duration {
+        requested_ms
+            .map(Duration::from_millis)
+            .unwrap_or(default)
+            .min(self.max_query_timeout)
+            .max(MIN_TIMEOUT)
+    }
+}
+
+/// Parses an environment variable, falling back to `default` when unset or invalid.
+pub fn env_parse<T: FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            log::warn!("Ignoring invalid value {value:?} for {name}");
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+fn env_millis(name: &str, default: Duration) -> Duration {
+    Duration::from_millis(env_parse(name, default.as_millis() as u64))
+}
+
+/// Reads a comma-separated environment variable, ignoring empty entries.
+pub fn env_list(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clamp_timeout() {
+        let config = ServerConfig::default();
+        assert_eq!(config.query_timeout(None), Duration::from_secs(30));
+        assert_eq!(config.query_timeout(Some(500)), Duration::from_millis(500));
+        assert_eq!(config.query_timeout(Some(0)), MIN_TIMEOUT);
+        assert_eq!(config.rpc_timeout(Some(600_000)), Duration::from_secs(120));
+
+        let no_max = ServerConfig {
+            max_query_timeout: Duration::ZERO,
+            ..ServerConfig::default()
+        };
+        assert_eq!(no_max.query_timeout(Some(500)), MIN_TIMEOUT);
+    }
+}
@@ -0,0 +1,299 @@
+use std::time::Duration;
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{Header, Status},
+    request::{FromRequest, Outcome},
+    response::{self, status, Responder},
+    Request, Response,
+};
+
+use crate::config::{env_list, env_parse, ServerConfig};
+
+/// Response headers browsers may read from cross-origin responses, beyond the CORS-safelisted
+/// ones.
+const EXPOSED_HEADERS: &str = "Content-Disposition, ETag, X-Cache, Retry-After, X-Row-Count, \
+                               X-Truncated, X-Row-Limit, X-Decode-Warnings, X-RateLimit-Limit, \
+                               X-RateLimit-Remaining, X-RateLimit-Reset, X-Concurrency-Limit, \
+                               X-Concurrency-Remaining";
+
+/// Which browser origins may call the API, and with what.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorsConfig {
+    /// Exact origins such as `https://app.sandworm.dev`, or `*` for any (`CORS_ALLOWED_ORIGINS`).
+    pub allowed_origins: Vec<String>,
+    /// Methods a preflight may ask for (`CORS_ALLOWED_METHODS`).
+    pub allowed_methods: Vec<String>,
+    /// Request headers a preflight may ask for, or `*` for any (`CORS_ALLOWED_HEADERS`).
+    pub allowed_headers: Vec<String>,
+    /// How long browsers may cache a preflight answer (`CORS_MAX_AGE_SECS`).
+    pub max_age: Duration,
+    /// Whether pages may send cookies or HTTP auth along (`CORS_ALLOW_CREDENTIALS`). Only
+    /// honoured with an explicit origin list.
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        let list = |items: &[&str]| items.iter().map(|s| s.to_string()).collect();
+        Self {
+            allowed_origins: list(&["*"]),
+            allowed_methods: list(&["GET", "POST", "OPTIONS"]),
+            allowed_headers: list(&["Content-Type", "Authorization", "X-API-Key"]),
+            max_age: Duration::from_secs(600),
+            allow_credentials: false,
+        }
+    }
+}
+
+impl CorsConfig {
+    /// The defaults, with each list replaced by its environment variable when that is set.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let list = |name, default: Vec<String>| {
+            let items = env_list(name);
+            if items.is_empty() {
+                default
+            } else {
+                items
+            }
+        };
+        let mut config = Self {
+            allowed_origins: list("CORS_ALLOWED_ORIGINS", defaults.allowed_origins),
+            allowed_methods: list("CORS_ALLOWED_METHODS", defaults.allowed_methods),
+            allowed_headers: list("CORS_ALLOWED_HEADERS", defaults.allowed_headers),
+            max_age: Duration::from_secs(env_parse(
+                "CORS_MAX_AGE_SECS",
+                defaults.max_age.as_secs(),
+            )),
+            allow_credentials: env_parse("CORS_ALLOW_CREDENTIALS", defaults.allow_credentials),
+        };
+        if config.allow_credentials && config.any_origin() {
+            log::warn!(
+                "Ignoring CORS_ALLOW_CREDENTIALS with a wildcard origin; list the origins that \
+                 may send credentials in CORS_ALLOWED_ORIGINS"
+            );
+            config.allow_credentials = false;
+        }
+        config
+    }
+
+    fn any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == "*")
+    }
+
+    /// Whether pages may send credentials. Never with a wildcard origin: echoing every origin
+    /// back with credentials would let any site make calls as the signed-in user.
+    fn allows_credentials(&self) -> bool {
+        self.allow_credentials && !self.any_origin()
+    }
+
+    /// The `Access-Control-Allow-Origin` value for a request from `origin`, or `None` when the
+    /// origin is not allowed. A wildcard answers `*`; listed origins are echoed back.
+    pub fn allow_origin(&self, origin: &str) -> Option<String> {
+        if self.any_origin() {
+            return Some("*".to_string());
+        }
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin))
+            .then(|| origin.to_string())
+    }
+
+    /// The preflight answer for a request that will use `method` and send `headers` (the
+    /// comma-separated `Access-Control-Request-Headers`), or why it is refused.
+    pub fn preflight(&self, method: &str, headers: &str) -> Result<Vec<Header<'static>>, String> {
+        if !self
+            .allowed_methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method))
+        {
+            return Err(format!("method {method} is not allowed"));
+        }
+        let requested: Vec<&str> = headers
+            .split(',')
+            .map(str::trim)
+            .filter(|header| !header.is_empty())
+            .collect();
+        let any_header = self.allowed_headers.iter().any(|header| header == "*");
+        if let Some(header) = requested.iter().find(|header| {
+            !any_header
+                && !self
+                    .allowed_headers
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(header))
+        }) {
+            return Err(format!("header {header} is not allowed"));
+        }
+
+        let mut answer = vec![
+            Header::new(
+                "Access-Control-Allow-Methods",
+                self.allowed_methods.join(", "),
+            ),
+            Header::new("Access-Control-Max-Age", self.max_age.as_secs().to_string()),
+        ];
+        // Echoing the request's headers also covers a `*` allow-list, which browsers take
+        // literally on credentialed requests.
+        if !requested.is_empty() {
+            answer.push(Header::new(
+                "Access-Control-Allow-Headers",
+                requested.join(", "),
+            ));
+        }
+        Ok(answer)
+    }
+}
+
+/// Adds `Access-Control-Allow-Origin` and related headers to responses for allowed origins.
+/// Responses to other origins go out without them, so browsers keep the body from the page.
+pub struct Cors;
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "Attaching CORS headers to responses",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(config) = request.rocket().state::<ServerConfig>() else {
+            return;
+        };
+        let cors = &config.cors;
+        // The answer depends on the origin unless every origin gets `*`.
+        if !cors.any_origin() {
+            response.adjoin_header(Header::new("Vary", "Origin"));
+        }
+        let Some(origin) = request.headers().get_one("Origin") else {
+            return;
+        };
+        let Some(allowed) = cors.allow_origin(origin) else {
+            return;
+        };
+        response.set_header(Header::new("Access-Control-Allow-Origin", allowed));
+        response.set_header(Header::new(
+            "Access-Control-Expose-Headers",
+            EXPOSED_HEADERS,
+        ));
+        if cors.allows_credentials() {
+            response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+        }
+    }
+}
+
+/// An accepted CORS preflight (`OPTIONS` with `Origin` and `Access-Control-Request-Method`).
+/// Responds with 204 and the allowed methods and headers; [`Cors`] adds the origin.
+pub struct Preflight(Vec<Header<'static>>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Preflight {
+    type Error = status::Custom<String>;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(config) = request.rocket().state::<ServerConfig>() else {
+            let error = "server state is not configured".to_string();
+            return refuse(Status::InternalServerError, error);
+        };
+        let headers = request.headers();
+        let (Some(origin), Some(method)) = (
+            headers.get_one("Origin"),
+            headers.get_one("Access-Control-Request-Method"),
+        ) else {
+            let error = "not a CORS preflight request".to_string();
+            return refuse(Status::BadRequest, error);
+        };
+        if config.cors.allow_origin(origin).is_none() {
+            let error = format!("origin {origin} is not allowed");
+            return refuse(Status::Forbidden, error);
+        }
+        let requested = headers
+            .get_one("Access-Control-Request-Headers")
+            .unwrap_or("");
+        match config.cors.preflight(method, requested) {
+            Ok(answer) => Outcome::Success(Preflight(answer)),
+            Err(error) => refuse(Status::Forbidden, error),
+        }
+    }
+}
+
+fn refuse(status: Status, error: String) -> Outcome<Preflight, status::Custom<String>> {
+    Outcome::Error((status, status::Custom(status, error)))
+}
+
+impl<'r> Responder<'r, 'static> for Preflight {
+    fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.status(Status::NoContent);
+        for header in self.0 {
+            response.header(header);
+        }
+        response.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow_origin() {
+        let open = CorsConfig::default();
+        assert_eq!(open.allow_origin("https://a.dev"), Some("*".to_string()));
+
+        let locked = CorsConfig {
+            allowed_origins: vec!["https://app.sandworm.dev/".to_string()],
+            allow_credentials: true,
+            ..CorsConfig::default()
+        };
+        assert_eq!(
+            locked.allow_origin("https://app.sandworm.dev"),
+            Some("https://app.sandworm.dev".to_string())
+        );
+        assert_eq!(locked.allow_origin("https://evil.dev"), None);
+
+        assert!(locked.allows_credentials());
+
+        // A wildcard never reflects the origin, and never with credentials.
+        let credentialed = CorsConfig {
+            allow_credentials: true,
+            ..CorsConfig::default()
+        };
+        assert_eq!(
+            credentialed.allow_origin("https://a.dev"),
+            Some("*".to_string())
+        );
+        assert!(!credentialed.allows_credentials());
+    }
+
+    #[test]
+    fn test_preflight() {
+        let config = CorsConfig::default();
+        let answer = config.preflight("post", "content-type, x-api-key").unwrap();
+        let value = |name| {
+            answer
+                .iter()
+                .find(|header| header.name() == name)
+                .map(|header| header.value().to_string())
+        };
+        assert_eq!(
+            value("Access-Control-Allow-Methods").as_deref(),
+            Some("GET, POST, OPTIONS")
+        );
+        assert_eq!(
+            value("Access-Control-Allow-Headers").as_deref(),
+            Some("content-type, x-api-key")
+        );
+        assert_eq!(value("Access-Control-Max-Age").as_deref(), Some("600"));
+
+        assert!(config.preflight("DELETE", "").is_err());
+        assert!(config.preflight("GET", "X-Custom").is_err());
+        let any_header = CorsConfig {
+            allowed_headers: vec!["*".to_string()],
+            ..CorsConfig::default()
+        };
+        assert!(any_header.preflight("GET", "X-Custom").is_ok());
+    }
+}
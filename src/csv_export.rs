@@ -0,0 +1,190 @@
+use std::borrow::Cow;
+
+use serde_json::Value;
+use sqlx::any::{Any, AnyRow};
+use sqlx::{Column, Describe, Row};
+
+use crate::sql_to_json::{sql_to_json_or_null, DecodeWarning, JsonOptions};
+
+/// Builds an RFC 4180 document: CRLF line endings, and fields quoted only when they contain a
+/// comma, a quote or a line break.
+#[derive(Default)]
+pub struct CsvWriter {
+    out: String,
+}
+
+impl CsvWriter {
+    pub fn write_record<I, S>(&mut self, fields: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for (i, field) in fields.into_iter().enumerate() {
+            if i > 0 {
+                self.out.push(',');
+            }
+            let field = field.as_ref();
+            if field.contains([',', '"', '\r', '\n']) {
+                self.out.push('"');
+                self.out.push_str(&field.replace('"', "\"\""));
+                self.out.push('"');
+            } else {
+                self.out.push_str(field);
+            }
+        }
+        self.out.push_str("\r\n");
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+/// The text of one cell. Strings are written as-is, nulls as an empty field, and arrays or
+/// objects as JSON text.
+pub fn cell(value: &Value) -> Cow<'_, str> {
+    match value {
+        Value::Null => Cow::Borrowed(""),
+        Value::String(s) => Cow::Borrowed(s),
+        other => Cow::Owned(other.to_string()),
+    }
+}
+
+/// Indexed rows with a header taken from the first row's columns, in select-list order. An
+/// empty result still gets its header from `describe`, and renders as an empty document when
+/// the statement could not be described. Cells that fail to decode are left empty and added
+/// to `warnings`.
+pub fn rows_to_csv(
+    rows: &[AnyRow],
+    describe: Option<&Describe<Any>>,
+    options: &JsonOptions,
+    warnings: &mut Vec<DecodeWarning>,
+) -> String {
+    let mut csv = CsvWriter::default();
+    let columns = match rows.first() {
+        Some(first) => first.columns(),
+        None => describe.map(|d| d.columns()).unwrap_or_default(),
+    };
+    if columns.is_empty() {
+        return csv.finish();
+    }
+    csv.write_record(columns.iter().map(|col| col.name()));
+    let mut errors = Vec::new();
+    for (index, row) in rows.iter().enumerate() {
+        let values: Vec<Value> = row
+            .columns()
+            .iter()
+            .map(|col| sql_to_json_or_null(row, col, options, &mut errors))
+            .collect();
+        csv.write_record(values.iter().map(cell));
+        warnings.extend(
+            errors
+                .drain(..)
+                .map(|error| DecodeWarning { row: index, error }),
+        );
+    }
+    csv.finish()
+}
+
+/// Renders the tables in a serialized RPC [`QueryResult`](crate::query::QueryResult), one per
+/// statement. A table is the first non-empty array of objects under a statement's `result`;
+/// its header is every key seen across its rows, in first-seen order. When a program returns
+/// several tables they are written one after another, separated by an empty line.
+pub fn tables_to_csv(result: &Value) -> String {
+    let mut tables = Vec::new();
+    for statement in result["data"].as_array().into_iter().flatten() {
+        find_tables(statement.get("result").unwrap_or(statement), &mut tables);
+    }
+
+    let mut csv = CsvWriter::default();
+    for (i, rows) in tables.into_iter().enumerate() {
+        if i > 0 {
+            csv.out.push_str("\r\n");
+        }
+        let mut header: Vec<&str> = Vec::new();
+        for row in rows.iter().filter_map(Value::as_object) {
+            for key in row.keys() {
+                if !header.contains(&key.as_str()) {
+                    header.push(key);
+                }
+            }
+        }
+        csv.write_record(&header);
+        for row in rows {
+            csv.write_record(header.iter().map(|key| cell(&row[*key])));
+        }
+    }
+    csv.finish()
+}
+
+fn find_tables<'a>(value: &'a Value, tables: &mut Vec<&'a [Value]>) {
+    match value {
+        Value::Array(items) if !items.is_empty() && items.iter().all(Value::is_object) => {
+            tables.push(items)
+        }
+        Value::Array(items) => items.iter().for_each(|item| find_tables(item, tables)),
+        Value::Object(map) => map.values().for_each(|item| find_tables(item, tables)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_to_json::test_database_url;
+    use serde_json::json;
+    use sqlx::{Connection, Executor};
+
+    #[test]
+    fn test_fields_are_quoted_when_needed() {
+        let mut csv = CsvWriter::default();
+        csv.write_record(["plain", "a,b", "say \"hi\"", "two\nlines", ""]);
+        assert_eq!(
+            csv.finish(),
+            "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",\r\n"
+        );
+    }
+
+    #[test]
+    fn test_cells() {
+        assert_eq!(cell(&Value::Null), "");
+        assert_eq!(cell(&json!("0xabc")), "0xabc");
+        assert_eq!(cell(&json!(12.5)), "12.5");
+        assert_eq!(cell(&json!(true)), "true");
+        assert_eq!(cell(&json!({"a": [1, 2]})), r#"{"a":[1,2]}"#);
+    }
+
+    #[tokio::test]
+    async fn test_empty_rows_keep_header() -> anyhow::Result<()> {
+        let mut c = sqlx::AnyConnection::connect(&test_database_url()).await?;
+        let sql = "SELECT 1 AS id, 'a' AS name WHERE 1 = 0";
+        let describe = (&mut c).describe(sql).await?;
+        let options = JsonOptions::default();
+        let mut warnings = Vec::new();
+        assert_eq!(
+            rows_to_csv(&[], Some(&describe), &options, &mut warnings),
+            "id,name\r\n"
+        );
+        assert_eq!(rows_to_csv(&[], None, &options, &mut warnings), "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rpc_tables() {
+        let result = json!({
+            "type": "Eql",
+            "data": [
+                {"result": {"account": [
+                    {"address": "0x1", "balance": "10"},
+                    {"address": "0x2", "nonce": 3}
+                ]}},
+                {"result": {"block": [{"number": 1, "tags": ["a", "b"]}]}}
+            ]
+        });
+        assert_eq!(
+            tables_to_csv(&result),
+            "address,balance,nonce\r\n0x1,10,\r\n0x2,,3\r\n\r\nnumber,tags\r\n1,\"[\"\"a\"\",\"\"b\"\"]\"\r\n"
+        );
+        assert_eq!(tables_to_csv(&json!({"type": "Eql", "data": []})), "");
+    }
+}
@@ -0,0 +1,236 @@
+use std::fmt;
+use std::time::Duration;
+
+use futures::{StreamExt, TryStreamExt};
+use sqlx::any::{Any, AnyKind, AnyPool, AnyRow};
+use sqlx::pool::PoolConnection;
+use sqlx::{Describe, Executor};
+use tokio::sync::mpsc;
+use tokio::time::{timeout_at, Instant};
+
+use crate::params::BoundQuery;
+
+/// How long the client side waits past the deadline for the server to cancel the statement
+/// itself, which leaves the connection reusable.
+const SERVER_CANCEL_GRACE: Duration = Duration::from_millis(500);
+
+/// Postgres `query_canceled`, raised when `statement_timeout` fires.
+const PG_QUERY_CANCELED: &str = "57014";
+
+/// Rows buffered between a streaming query and the response body.
+const STREAM_BUFFER: usize = 256;
+
+#[derive(Debug)]
+pub enum ExecError {
+    Timeout(Duration),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecError::Timeout(timeout) => {
+                write!(f, "Query exceeded the {} ms timeout", timeout.as_millis())
+            }
+            ExecError::Database(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for ExecError {
+    fn from(e: sqlx::Error) -> Self {
+        ExecError::Database(e)
+    }
+}
+
+/// Rows read for one query, with `truncated` set when more than the cap were available.
+pub struct Fetched {
+    pub rows: Vec<AnyRow>,
+    pub truncated: bool,
+    /// The statement's result columns as reported by the driver. Only looked up when no rows
+    /// came back to read the columns from, and `None` when the driver could not describe them.
+    pub describe: Option<Describe<Any>>,
+}
+
+/// Runs `bound` and collects at most `max_rows` of its rows, giving up once `timeout` has
+/// elapsed. One extra row is read to find out whether the result was truncated.
+pub async fn fetch_all(
+    pool: &AnyPool,
+    bound: &BoundQuery,
+    timeout: Duration,
+    max_rows: usize,
+) -> Result<Fetched, ExecError> {
+    let mut session = Session::start(pool, timeout).await?;
+    let cutoff = session.cutoff;
+    let fetch = bound
+        .query()
+        .fetch(&mut *session.conn)
+        .take(max_rows.saturating_add(1))
+        .try_collect::<Vec<_>>();
+    let rows = match timeout_at(cutoff, fetch).await {
+        Ok(rows) => rows,
+        Err(_elapsed) => {
+            session.abandon();
+            return Err(ExecError::Timeout(timeout));
+        }
+    };
+    let describe = match &rows {
+        Ok(rows) if rows.is_empty() => {
+            match timeout_at(cutoff, (&mut *session.conn).describe(&bound.sql)).await {
+                Ok(Ok(describe)) => Some(describe),
+                Ok(Err(e)) => {
+                    log::warn!("Could not describe query columns: {e}");
+                    None
+                }
+                Err(_elapsed) => {
+                    session.abandon();
+                    return Err(ExecError::Timeout(timeout));
+                }
+            }
+        }
+        _ => None,
+    };
+    session.finish().await;
+
+    let mut rows = rows.map_err(|e| classify(e, timeout))?;
+    let truncated = rows.len() > max_rows;
+    rows.truncate(max_rows);
+    Ok(Fetched {
+        rows,
+        truncated,
+        describe,
+    })
+}
+
+/// Runs `bound` on a background task and hands its rows over as they arrive. The channel is
+/// bounded, so a slow reader holds the query back instead of the rows piling up here; it
+/// closes after the last row or the first error. Dropping the receiver stops the query.
+///
+/// With `max_rows`, reading stops one row past it, so the reader can tell whether the result
+/// was cut off.
+pub fn stream(
+    pool: AnyPool,
+    bound: BoundQuery,
+    timeout: Duration,
+    max_rows: Option<usize>,
+) -> mpsc::Receiver<Result<AnyRow, ExecError>> {
+    let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+    let max_rows = max_rows.map_or(usize::MAX, |max_rows| max_rows.saturating_add(1));
+    tokio::spawn(async move {
+        if let Err(e) = stream_into(&pool, &bound, timeout, max_rows, &tx).await {
+            let _ = tx.send(Err(e)).await;
+        }
+    });
+    rx
+}
+
+async fn stream_into(
+    pool: &AnyPool,
+    bound: &BoundQuery,
+    timeout: Duration,
+    max_rows: usize,
+    tx: &mpsc::Sender<Result<AnyRow, ExecError>>,
+) -> Result<(), ExecError> {
+    let mut session = Session::start(pool, timeout).await?;
+    let cutoff = session.cutoff;
+    let mut rows = bound.query().fetch(&mut *session.conn).take(max_rows);
+    let outcome = loop {
+        match timeout_at(cutoff, rows.next()).await {
+            Ok(Some(Ok(row))) => {
+                if tx.send(Ok(row)).await.is_err() {
+                    // The client went away; nobody is left to report to.
+                    break Err(None);
+                }
+            }
+            Ok(Some(Err(e))) => break Err(Some(classify(e, timeout))),
+            Ok(None) => break Ok(()),
+            Err(_elapsed) => break Err(Some(ExecError::Timeout(timeout))),
+        }
+    };
+    drop(rows);
+
+    match outcome {
+        Ok(()) => {
+            session.finish().await;
+            Ok(())
+        }
+        Err(None) => {
+            session.abandon();
+            Ok(())
+        }
+        Err(Some(e)) => {
+            session.abandon();
+            Err(e)
+        }
+    }
+}
+
+/// A pooled connection with the query deadline applied to it.
+///
+/// On Postgres the session's `statement_timeout` is set as well, so the backend cancels the
+/// statement instead of running it to completion after we stop waiting. Other drivers only
+/// get the client-side deadline.
+struct Session {
+    conn: PoolConnection<Any>,
+    server_side: bool,
+    /// When the client side stops waiting: the deadline, plus a grace period on Postgres.
+    cutoff: Instant,
+}
+
+impl Session {
+    async fn start(pool: &AnyPool, timeout: Duration) -> Result<Self, ExecError> {
+        let deadline = Instant::now() + timeout;
+        let mut conn = timeout_at(deadline, pool.acquire())
+            .await
+            .map_err(|_| ExecError::Timeout(timeout))??;
+
+        let server_side = pool.any_kind() == AnyKind::Postgres;
+        if server_side {
+            let remaining = deadline
+                .saturating_duration_since(Instant::now())
+                .as_millis()
+                .max(1);
+            sqlx::query(&format!("SET statement_timeout = {remaining}"))
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        let cutoff = if server_side {
+            deadline + SERVER_CANCEL_GRACE
+        } else {
+            deadline
+        };
+        Ok(Self {
+            conn,
+            server_side,
+            cutoff,
+        })
+    }
+
+    /// Hands the connection back to the pool once the statement has completed.
+    async fn finish(mut self) {
+        if self.server_side
+            && sqlx::query("RESET statement_timeout")
+                .execute(&mut *self.conn)
+                .await
+                .is_err()
+        {
+            self.abandon();
+        }
+    }
+
+    /// Closes the connection instead of returning it, for when it may still be mid-query.
+    fn abandon(self) {
+        drop(self.conn.detach());
+    }
+}
+
+fn classify(e: sqlx::Error, timeout: Duration) -> ExecError {
+    let canceled =
+        matches!(&e, sqlx::Error::Database(db) if db.code().as_deref() == Some(PG_QUERY_CANCELED));
+    if canceled {
+        ExecError::Timeout(timeout)
+    } else {
+        ExecError::Database(e)
+    }
+}
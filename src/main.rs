@@ -1,61 +1,46 @@
 use rocket::{
-    fairing::{Fairing, Info, Kind},
-    http::{Header, Status},
-    response::{content::RawJson, status, Response},
-    Request, State,
+    http::Status,
+    response::{content::RawJson, status},
+    serde::json::{self, Json},
+    Build, Rocket, State,
 };
 
-use serde::Serialize;
-use eql_core::{
-    common::query_result::QueryResult as EqlQueryResult, interpreter::Interpreter as EQlInterpreter,
-};
-use serde_json::{json, Value};
-use sql_to_json::row_to_json;
-use sui_ql_core::{
-    common::query_result::QueryResult as SuiQueryResult,
-    interpreter::Interpreter as SuiQlInterpreter,
-};
+use serde_json::json;
 
 use dotenv::dotenv;
 use sqlx::any::AnyPool;
-use crate::utils::json_error;
-
-
-pub struct CORS;
-
-#[rocket::async_trait]
-impl Fairing for CORS {
-    fn info(&self) -> Info {
-        Info {
-            name: "Attaching CORS headers to responses",
-            kind: Kind::Response,
-        }
-    }
+use crate::auth::{ApiKey, AuthError};
+use crate::config::ServerConfig;
+use crate::cors::{Cors, Preflight};
+use crate::params::QueryParams;
+use crate::query::{QueryOptions, QueryRequest, QueryType};
+use crate::rate_limit::{Permit, RateLimitHeaders, RateLimited, RateLimiter};
+use crate::response::QueryResponse;
+use crate::result_cache::{CacheMode, ConditionalGet, ResultCache};
+use crate::utils::json_response;
 
-    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
-        response.set_header(Header::new(
-            "Access-Control-Allow-Methods",
-            "POST, GET, PATCH, OPTIONS",
-        ));
-        response.set_header(Header::new("Access-Control-Allow-Headers", "*"));
-        response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
-    }
-}
 
+mod arrow_export;
+mod auth;
+mod chains;
+mod config;
+mod cors;
+mod csv_export;
+mod indexed;
+mod params;
+mod pg_types;
+mod query;
+mod rate_limit;
+mod response;
+mod result_cache;
 mod utils;
+mod sql_guard;
+mod sql_rewrite;
 mod sql_to_json;
 
 #[macro_use]
 extern crate rocket;
 
-#[derive(Serialize)]
-#[serde(tag = "type", content = "data")]
-pub enum QueryResult {
-    Sui(Vec<SuiQueryResult>),
-    Eql(Vec<EqlQueryResult>),
-}
-
 #[get("/")]
 fn index() -> &'static str {
     "Sandworm API Server is up and running!"
@@ -66,12 +51,27 @@ fn health() -> RawJson<String> {
     RawJson("{\"status\":\"healthy\"}".to_string())
 }
 
-#[get("/run?<type_param>&<query>")]
+/// Compatibility shim for clients that still send SQL in the query string; new clients
+/// should use `POST /v1/query`. `cache=bypass` skips the result cache.
+#[get("/run?<type_param>&<query>&<cache>")]
 async fn run_query(
     query: &str,
     type_param: &str,
+    cache: Option<&str>,
+    key: Result<ApiKey, AuthError>,
+    permit: Result<Permit, RateLimited>,
     pool: &State<AnyPool>,
-) -> status::Custom<RawJson<String>> {
+    result_cache: &State<ResultCache>,
+    config: &State<ServerConfig>,
+) -> QueryResponse {
+    let ApiKey(policy) = match key {
+        Ok(key) => key,
+        Err(e) => return e.response().into(),
+    };
+    let permit = match permit {
+        Ok(permit) => permit,
+        Err(e) => return e.response().into(),
+    };
     if !matches!(type_param, "rpc" | "indexed") {
         return status::Custom(
             Status::BadRequest,
@@ -79,72 +79,89 @@ async fn run_query(
                 r#"{"error": "Invalid type. Supported values are: 'rpc' or 'indexed'."} "#
                     .to_string(),
             ),
-        );
-    }
-
-    let query = &utils::remove_sql_comments(query);
-
-    if !utils::is_query_only(query.to_owned()) {
-        return status::Custom(
-            Status::BadRequest,
-            RawJson(
-                r#"{"error": "Only SELECT queries are allowed. CREATE, DROP, INSERT, UPDATE, DELETE, and other write ops are blocked."} "#
-                    .to_string(),
-            ),
-        );
+        )
+        .into();
     }
 
-    if type_param == "rpc" {
-        let (_label, result): (&str, Result<QueryResult, _>) = if utils::is_sui_rpc_query(query) {
-            let res = SuiQlInterpreter::run_program(query).await.map(QueryResult::Sui);
-            ("SUI_QL", res)
-        } else {
-            let res = EQlInterpreter::run_program(query).await.map(QueryResult::Eql);
-            ("EQL", res)
-        };
-
-        match result {
-            Ok(data) => match serde_json::to_string(&data) {
-                Ok(json) => status::Custom(Status::Ok, RawJson(json)),
-                Err(err) => json_error(err),
-            },
-            Err(err) => json_error(err),
+    let cache = match cache {
+        None | Some("use") => CacheMode::Use,
+        Some("bypass") => CacheMode::Bypass,
+        Some(_) => {
+            return json_response(
+                Status::BadRequest,
+                json!({ "error": "Invalid cache. Supported values are: 'use' or 'bypass'." }),
+            )
+            .into()
         }
-    } else {
-        let flattened_query = utils::flatten_known_chain_tables(&query);
-        if let Err(e) = gluesql::prelude::parse(&flattened_query) {
-            return json_error(e);
-        }
-
-        let rows_json: Vec<Value> = match sqlx::query(&flattened_query).fetch_all(&**pool).await {
-            Ok(rows) => rows.into_iter().map(|row| row_to_json(&row)).collect(),
-            Err(e) => return json_error(e),
-        };
+    };
 
-        let wrapped_data: Vec<Value> = rows_json.into_iter().map(|row| json!(row)).collect();
+    let query_type = if type_param == "rpc" {
+        QueryType::Rpc
+    } else {
+        QueryType::Indexed
+    };
+    let request = QueryRequest {
+        query_type: Some(query_type),
+        engine: None,
+        query: query.to_string(),
+        params: QueryParams::default(),
+        options: QueryOptions {
+            cache,
+            ..QueryOptions::default()
+        },
+    };
+    query::run(request, &policy, pool, result_cache, config)
+        .await
+        .holding(permit)
+}
 
-        status::Custom(
-            Status::Ok,
-            RawJson(
-                json!({
-                    "type": "Wql",
-                    "data": [
-                        {
-                            "result": {
-                                "indexed": wrapped_data
-                            }
-                        }
-                    ]
-                })
-                .to_string(),
-            ),
-        )
+/// Same as `/run`, but takes the query in a JSON body so long SQL stays out of URLs and
+/// access logs.
+#[post("/v1/query", format = "json", data = "<request>")]
+async fn post_query(
+    request: Result<Json<QueryRequest>, json::Error<'_>>,
+    key: Result<ApiKey, AuthError>,
+    permit: Result<Permit, RateLimited>,
+    pool: &State<AnyPool>,
+    result_cache: &State<ResultCache>,
+    config: &State<ServerConfig>,
+) -> QueryResponse {
+    let ApiKey(policy) = match key {
+        Ok(key) => key,
+        Err(e) => return e.response().into(),
+    };
+    let permit = match permit {
+        Ok(permit) => permit,
+        Err(e) => return e.response().into(),
+    };
+    match request {
+        Ok(Json(request)) => query::run(request, &policy, pool, result_cache, config)
+            .await
+            .holding(permit),
+        Err(e) => json_response(Status::BadRequest, json!({ "error": e.to_string() })).into(),
     }
 }
 
 #[options("/<_..>")]
-fn preflight_handler() -> &'static str {
-    ""
+fn preflight_handler(
+    preflight: Result<Preflight, status::Custom<String>>,
+) -> Result<Preflight, status::Custom<String>> {
+    preflight
+}
+
+fn rocket(pool: AnyPool, config: ServerConfig) -> Rocket<Build> {
+    rocket::build()
+        .manage(pool)
+        .manage(RateLimiter::new(config.rate_limit.clone()))
+        .manage(ResultCache::new(config.cache.clone()))
+        .manage(config)
+        .attach(Cors)
+        .attach(RateLimitHeaders)
+        .attach(ConditionalGet)
+        .mount(
+            "/",
+            routes![index, run_query, post_query, health, preflight_handler],
+        )
 }
 
 #[rocket::main]
@@ -160,12 +177,205 @@ async fn main() -> Result<(), rocket::Error> {
         .await
         .expect("Could not connect to DB");
 
-    rocket::build()
-        .manage(pool)
-        .attach(CORS)
-        .mount("/", routes![index, run_query, health, preflight_handler])
-        .launch()
-        .await?;
+    let config = ServerConfig::from_env();
+    if config.require_api_key {
+        auth::ensure_table(&pool)
+            .await
+            .expect("Could not create the api_keys table");
+    }
+
+    rocket(pool, config).launch().await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::{ContentType, Header};
+    use rocket::local::asynchronous::{Client, LocalResponse};
+    use serde_json::Value;
+    use sqlx::any::AnyPoolOptions;
+
+    async fn client() -> Client {
+        let pool = AnyPool::connect("sqlite::memory:").await.unwrap();
+        let config = ServerConfig {
+            require_api_key: false,
+            ..ServerConfig::default()
+        };
+        Client::tracked(rocket(pool, config)).await.unwrap()
+    }
+
+    /// A client that requires keys, with `key` stored under the given restrictions, over a
+    /// database with a `numbers` table holding 1 to 3.
+    async fn keyed_client(key: &str, chains: Option<&str>, max_rows: Option<i64>) -> Client {
+        // Every connection to `sqlite::memory:` opens a database of its own.
+        let pool = AnyPoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        auth::ensure_table(&pool).await.unwrap();
+        for statement in [
+            "CREATE TABLE numbers (n INTEGER)",
+            "INSERT INTO numbers (n) VALUES (1), (2), (3)",
+        ] {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+        sqlx::query("INSERT INTO api_keys (key_hash, name, chains, max_rows) VALUES (?, ?, ?, ?)")
+            .bind(auth::hash_key(key))
+            .bind("test")
+            .bind(chains)
+            .bind(max_rows)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let config = ServerConfig {
+            require_api_key: true,
+            ..ServerConfig::default()
+        };
+        Client::tracked(rocket(pool, config)).await.unwrap()
+    }
+
+    async fn send<'c>(client: &'c Client, body: &str) -> LocalResponse<'c> {
+        client
+            .post("/v1/query")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .await
+    }
+
+    async fn post(client: &Client, body: &str) -> (Status, Value) {
+        let response = send(client, body).await;
+        let status = response.status();
+        (status, response.into_json().await.unwrap())
+    }
+
+    #[rocket::async_test]
+    async fn test_post_query() {
+        let client = client().await;
+        let (status, body) = post(
+            &client,
+            r#"{"type": "indexed", "query": "SELECT 1 AS one", "options": {"timeout_ms": 5000}}"#,
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["data"][0]["result"]["indexed"][0]["one"], 1);
+        assert_eq!(body["row_count"], 1);
+
+        // Comment markers inside literals are part of the value.
+        let query = "SELECT 'https://x' AS url, 'a--b' AS note -- trailing";
+        let body = json!({"type": "indexed", "query": query}).to_string();
+        let (status, body) = post(&client, &body).await;
+        assert_eq!(status, Status::Ok);
+        let row = &body["data"][0]["result"]["indexed"][0];
+        assert_eq!(row["url"], "https://x");
+        assert_eq!(row["note"], "a--b");
+    }
+
+    #[rocket::async_test]
+    async fn test_post_query_rejects_bad_bodies() {
+        let client = client().await;
+        let (status, body) = post(
+            &client,
+            r#"{"type": "indexed", "query": "SELECT 1", "sql": "SELECT 2"}"#,
+        )
+        .await;
+        assert_eq!(status, Status::BadRequest);
+        assert!(body["error"].as_str().unwrap().contains("unknown field"));
+
+        let (status, body) = post(&client, r#"{"type": "indexed", "query": "#).await;
+        assert_eq!(status, Status::BadRequest);
+        assert!(body["error"].is_string());
+    }
+
+    #[rocket::async_test]
+    async fn test_decode_failures() {
+        let client = client().await;
+        // Invalid UTF-8 in a TEXT value cannot be decoded as a string.
+        let body = |format: &str, strict: bool| {
+            json!({
+                "type": "indexed",
+                "query": "SELECT 1 AS id, CAST(x'ff' AS TEXT) AS bad",
+                "options": {"format": format, "strict": strict},
+            })
+            .to_string()
+        };
+
+        let (status, json) = post(&client, &body("json", false)).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(json["data"][0]["result"]["indexed"][0]["bad"], Value::Null);
+        assert_eq!(json["warnings"][0]["column"], "bad");
+        assert_eq!(json["warnings"][0]["row"], 0);
+
+        let ndjson = send(&client, &body("ndjson", false)).await;
+        assert_eq!(ndjson.status(), Status::Ok);
+        let text = ndjson.into_string().await.unwrap();
+        let lines: Vec<Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["id"], 1);
+        assert_eq!(lines[1]["warning"]["column"], "bad");
+
+        for format in ["csv", "arrow"] {
+            let download = send(&client, &body(format, false)).await;
+            assert_eq!(download.status(), Status::Ok, "{format}");
+            assert_eq!(
+                download.headers().get_one("X-Decode-Warnings"),
+                Some("1"),
+                "{format}"
+            );
+            if format == "csv" {
+                assert_eq!(download.into_string().await.unwrap(), "id,bad\r\n1,\r\n");
+            }
+        }
+
+        for format in ["json", "ndjson", "csv", "arrow"] {
+            let (status, json) = post(&client, &body(format, true)).await;
+            assert_eq!(status, Status::UnprocessableEntity, "{format}");
+            assert_eq!(json["warning"]["column"], "bad", "{format}");
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_key_restrictions() {
+        let client = keyed_client("secret", Some("eth"), Some(2)).await;
+        let keyed = |body: Value| {
+            client
+                .post("/v1/query")
+                .header(ContentType::JSON)
+                .header(Header::new("X-API-Key", "secret"))
+                .body(body.to_string())
+                .dispatch()
+        };
+
+        // The key's row cap holds for streamed results.
+        let query = "SELECT n FROM numbers ORDER BY n";
+        let streamed = json!({"type": "indexed", "query": query, "options": {"format": "ndjson"}});
+        let ndjson = keyed(streamed).await;
+        assert_eq!(ndjson.status(), Status::Ok);
+        let text = ndjson.into_string().await.unwrap();
+        let lines: Vec<Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1]["n"], 2);
+        assert_eq!(lines[2], json!({"truncated": true, "row_limit": 2}));
+
+        // A key limited to some chains has to name them.
+        let unnamed = keyed(json!({"type": "rpc", "query": "GET balance FROM account 0x1"})).await;
+        assert_eq!(unnamed.status(), Status::Forbidden);
+        let body: Value = unnamed.into_json().await.unwrap();
+        assert_eq!(body["code"], "chain_required");
+
+        let other = json!({"type": "rpc", "query": "GET balance FROM account 0x1 ON base"});
+        let other = keyed(other).await;
+        assert_eq!(other.status(), Status::Forbidden);
+        let body: Value = other.into_json().await.unwrap();
+        assert_eq!(body["code"], "chain_not_allowed");
+    }
+}
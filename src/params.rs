@@ -0,0 +1,551 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::tokenizer::{Location, Token, TokenWithLocation, Tokenizer};
+use sqlx::any::{Any, AnyArguments, AnyKind};
+use sqlx::query::Query;
+
+/// Values for the placeholders of an indexed query: an object for named placeholders
+/// (`$address`, `:block`) or an array for positional ones (`$1`, `$2`).
+///
+/// Each value is either a bare JSON scalar, whose type is inferred, or an object declaring
+/// the SQL type explicitly: `{"type": "bigint", "value": "18446744073709551615"}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum QueryParams {
+    Named(Map<String, Value>),
+    Positional(Vec<Value>),
+}
+
+impl Default for QueryParams {
+    fn default() -> Self {
+        QueryParams::Named(Map::new())
+    }
+}
+
+impl QueryParams {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            QueryParams::Named(map) => map.is_empty(),
+            QueryParams::Positional(list) => list.is_empty(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamType {
+    Text,
+    SmallInt,
+    Int,
+    BigInt,
+    Real,
+    Double,
+    Bool,
+}
+
+impl ParamType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name.to_lowercase().as_str() {
+            "text" | "varchar" | "string" => ParamType::Text,
+            "smallint" | "int2" => ParamType::SmallInt,
+            "int" | "integer" | "int4" => ParamType::Int,
+            "bigint" | "int8" => ParamType::BigInt,
+            "real" | "float4" => ParamType::Real,
+            "double" | "double precision" | "float" | "float8" => ParamType::Double,
+            "bool" | "boolean" => ParamType::Bool,
+            _ => return None,
+        })
+    }
+}
+
+/// A parameter value checked against its declared type, ready to be bound.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamValue {
+    Null(ParamType),
+    Text(String),
+    SmallInt(i16),
+    Int(i32),
+    BigInt(i64),
+    Real(f32),
+    Double(f64),
+    Bool(bool),
+}
+
+impl ParamValue {
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let (param_type, value) = match value {
+            Value::Object(declared) => {
+                let type_name = declared
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .ok_or("a declared parameter needs a \"type\"")?;
+                let param_type = ParamType::parse(type_name)
+                    .ok_or_else(|| format!("unsupported parameter type {type_name:?}"))?;
+                if let Some(key) = declared
+                    .keys()
+                    .find(|k| !["type", "value"].contains(&k.as_str()))
+                {
+                    return Err(format!("unexpected field {key:?}"));
+                }
+                (param_type, declared.get("value").unwrap_or(&Value::Null))
+            }
+            Value::String(_) => (ParamType::Text, value),
+            Value::Bool(_) => (ParamType::Bool, value),
+            Value::Number(n) if n.is_i64() => (ParamType::BigInt, value),
+            Value::Number(_) => (ParamType::Double, value),
+            Value::Null => {
+                return Err(
+                    "null needs a declared type, e.g. {\"type\": \"text\", \"value\": null}"
+                        .to_string(),
+                )
+            }
+            Value::Array(_) => return Err("arrays are not supported".to_string()),
+        };
+        Self::typed(param_type, value)
+    }
+
+    fn typed(param_type: ParamType, value: &Value) -> Result<Self, String> {
+        if value.is_null() {
+            return Ok(ParamValue::Null(param_type));
+        }
+        let mismatch = || format!("{value} is not a valid {param_type:?} value");
+        // Integers may be sent as strings so values above 2^53 survive JSON clients.
+        let integer = || match value {
+            Value::Number(n) => n.as_i64().ok_or_else(mismatch),
+            Value::String(s) => s.trim().parse::<i64>().map_err(|_| mismatch()),
+            _ => Err(mismatch()),
+        };
+        let float = || match value {
+            Value::Number(n) => n.as_f64().ok_or_else(mismatch),
+            Value::String(s) => s.trim().parse::<f64>().map_err(|_| mismatch()),
+            _ => Err(mismatch()),
+        };
+        Ok(match param_type {
+            ParamType::Text => ParamValue::Text(value.as_str().ok_or_else(mismatch)?.to_string()),
+            ParamType::SmallInt => {
+                ParamValue::SmallInt(integer()?.try_into().map_err(|_| mismatch())?)
+            }
+            ParamType::Int => ParamValue::Int(integer()?.try_into().map_err(|_| mismatch())?),
+            ParamType::BigInt => ParamValue::BigInt(integer()?),
+            ParamType::Real => ParamValue::Real(float()? as f32),
+            ParamType::Double => ParamValue::Double(float()?),
+            ParamType::Bool => ParamValue::Bool(value.as_bool().ok_or_else(mismatch)?),
+        })
+    }
+
+    fn bind<'q>(
+        &self,
+        query: Query<'q, Any, AnyArguments<'q>>,
+    ) -> Query<'q, Any, AnyArguments<'q>> {
+        match self.clone() {
+            ParamValue::Null(ParamType::Text) => query.bind(None::<String>),
+            ParamValue::Null(ParamType::SmallInt) => query.bind(None::<i16>),
+            ParamValue::Null(ParamType::Int) => query.bind(None::<i32>),
+            ParamValue::Null(ParamType::BigInt) => query.bind(None::<i64>),
+            ParamValue::Null(ParamType::Real) => query.bind(None::<f32>),
+            ParamValue::Null(ParamType::Double) => query.bind(None::<f64>),
+            ParamValue::Null(ParamType::Bool) => query.bind(None::<bool>),
+            ParamValue::Text(v) => query.bind(v),
+            ParamValue::SmallInt(v) => query.bind(v),
+            ParamValue::Int(v) => query.bind(v),
+            ParamValue::BigInt(v) => query.bind(v),
+            ParamValue::Real(v) => query.bind(v),
+            ParamValue::Double(v) => query.bind(v),
+            ParamValue::Bool(v) => query.bind(v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamError {
+    Missing(String),
+    Unused(String),
+    Invalid(String, String),
+    Mixed,
+    Syntax(String),
+}
+
+impl ParamError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParamError::Missing(_) => "missing_parameter",
+            ParamError::Unused(_) => "unused_parameter",
+            ParamError::Invalid(..) => "invalid_parameter",
+            ParamError::Mixed => "mixed_parameters",
+            ParamError::Syntax(_) => "syntax_error",
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let param = match self {
+            ParamError::Missing(name) | ParamError::Unused(name) | ParamError::Invalid(name, _) => {
+                Some(name)
+            }
+            _ => None,
+        };
+        json!({ "error": self.to_string(), "code": self.code(), "param": param })
+    }
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Missing(name) => write!(f, "No value was given for parameter {name}"),
+            ParamError::Unused(name) => write!(f, "Parameter {name} is not used in the query"),
+            ParamError::Invalid(name, reason) => {
+                write!(f, "Invalid value for parameter {name}: {reason}")
+            }
+            ParamError::Mixed => write!(f, "Named and positional parameters cannot be mixed"),
+            ParamError::Syntax(e) => write!(f, "Could not read query parameters: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+/// How the database driver spells a bind parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaceholderStyle {
+    /// `$1`, `$2`; a value referenced twice is bound once.
+    Numbered,
+    /// `?`; values are bound once per occurrence.
+    QuestionMark,
+    /// `@p1`, `@p2` (SQL Server).
+    AtP,
+}
+
+impl From<AnyKind> for PlaceholderStyle {
+    fn from(kind: AnyKind) -> Self {
+        match kind {
+            AnyKind::Postgres => PlaceholderStyle::Numbered,
+            AnyKind::MySql | AnyKind::Sqlite => PlaceholderStyle::QuestionMark,
+            AnyKind::Mssql => PlaceholderStyle::AtP,
+        }
+    }
+}
+
+/// A query rewritten to the driver's placeholder syntax, with its values in bind order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoundQuery {
+    pub sql: String,
+    pub values: Vec<ParamValue>,
+}
+
+impl BoundQuery {
+    pub fn query(&self) -> Query<'_, Any, AnyArguments<'_>> {
+        self.values
+            .iter()
+            .fold(sqlx::query(&self.sql), |query, value| value.bind(query))
+    }
+}
+
+/// A placeholder found in the query text, keyed by parameter name (`address`) or by its
+/// 1-based position rendered as a string (`"1"`).
+struct Placeholder {
+    key: String,
+    positional: bool,
+    start: usize,
+    end: usize,
+}
+
+/// Replaces `$name`, `:name` and `$N` placeholders in `sql` with the driver's syntax and
+/// checks `params` against them: every placeholder needs a value and every value must be
+/// used.
+pub fn bind_params(
+    sql: &str,
+    params: &QueryParams,
+    style: PlaceholderStyle,
+) -> Result<BoundQuery, ParamError> {
+    let placeholders = find_placeholders(sql)?;
+    if placeholders.is_empty() && params.is_empty() {
+        return Ok(BoundQuery {
+            sql: sql.to_string(),
+            values: vec![],
+        });
+    }
+
+    let positional = placeholders.iter().any(|p| p.positional);
+    if positional && placeholders.iter().any(|p| !p.positional) {
+        return Err(ParamError::Mixed);
+    }
+
+    let mut given: HashMap<String, &Value> = match params {
+        QueryParams::Named(map) => map.iter().map(|(k, v)| (k.clone(), v)).collect(),
+        QueryParams::Positional(list) => list
+            .iter()
+            .enumerate()
+            .map(|(i, v)| ((i + 1).to_string(), v))
+            .collect(),
+    };
+    let display = |key: &str| {
+        if positional {
+            format!("${key}")
+        } else {
+            key.to_string()
+        }
+    };
+    if !params.is_empty() && positional != matches!(params, QueryParams::Positional(_)) {
+        return Err(ParamError::Mixed);
+    }
+
+    let mut values = Vec::new();
+    let mut numbers: HashMap<&str, usize> = HashMap::new();
+    let mut rewritten = String::with_capacity(sql.len());
+    let mut cursor = 0;
+    for placeholder in &placeholders {
+        let raw = given
+            .get(&placeholder.key)
+            .ok_or_else(|| ParamError::Missing(display(&placeholder.key)))?;
+        let value = ParamValue::from_json(raw)
+            .map_err(|reason| ParamError::Invalid(display(&placeholder.key), reason))?;
+
+        let marker = match style {
+            PlaceholderStyle::QuestionMark => {
+                values.push(value);
+                "?".to_string()
+            }
+            PlaceholderStyle::Numbered | PlaceholderStyle::AtP => {
+                let next = numbers.len() + 1;
+                let number = *numbers.entry(&placeholder.key).or_insert_with(|| {
+                    values.push(value);
+                    next
+                });
+                if style == PlaceholderStyle::Numbered {
+                    format!("${number}")
+                } else {
+                    format!("@p{number}")
+                }
+            }
+        };
+        rewritten.push_str(&sql[cursor..placeholder.start]);
+        rewritten.push_str(&marker);
+        cursor = placeholder.end;
+    }
+    rewritten.push_str(&sql[cursor..]);
+
+    for placeholder in &placeholders {
+        given.remove(&placeholder.key);
+    }
+    let unused: BTreeSet<String> = given.keys().map(|k| display(k)).collect();
+    if let Some(name) = unused.into_iter().next() {
+        return Err(ParamError::Unused(name));
+    }
+
+    Ok(BoundQuery {
+        sql: rewritten,
+        values,
+    })
+}
+
+fn find_placeholders(sql: &str) -> Result<Vec<Placeholder>, ParamError> {
+    let tokens = Tokenizer::new(&PostgreSqlDialect {}, sql)
+        .tokenize_with_location()
+        .map_err(|e| ParamError::Syntax(e.to_string()))?;
+    let offsets = LineOffsets::new(sql);
+
+    let mut placeholders = Vec::new();
+    let mut bracket_depth = 0usize;
+    for (i, TokenWithLocation { token, location }) in tokens.iter().enumerate() {
+        match token {
+            Token::LBracket => bracket_depth += 1,
+            Token::RBracket => bracket_depth = bracket_depth.saturating_sub(1),
+            Token::Placeholder(text) if text.starts_with('$') => {
+                let name = &text[1..];
+                let positional = name.chars().all(|c| c.is_ascii_digit());
+                if positional && name.parse::<usize>().map_or(true, |n| n == 0) {
+                    return Err(ParamError::Syntax(format!("invalid placeholder {text}")));
+                }
+                let start = offsets.byte_offset(*location);
+                placeholders.push(Placeholder {
+                    key: if positional {
+                        name.trim_start_matches('0').to_string()
+                    } else {
+                        name.to_string()
+                    },
+                    positional,
+                    start,
+                    end: start + text.len(),
+                });
+            }
+            // `:name`, but not the `a:b` of an array slice.
+            Token::Colon if bracket_depth == 0 => {
+                if let Some(TokenWithLocation {
+                    token: Token::Word(word),
+                    location: word_location,
+                }) = tokens.get(i + 1)
+                {
+                    let start = offsets.byte_offset(*location);
+                    if word.quote_style.is_none()
+                        && offsets.byte_offset(*word_location) == start + 1
+                    {
+                        placeholders.push(Placeholder {
+                            key: word.value.clone(),
+                            positional: false,
+                            start,
+                            end: start + 1 + word.value.len(),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(placeholders)
+}
+
+/// Converts sqlparser's 1-based, character-counted line/column locations to byte offsets.
+struct LineOffsets<'a> {
+    sql: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineOffsets<'a> {
+    fn new(sql: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(sql.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineOffsets { sql, starts }
+    }
+
+    fn byte_offset(&self, location: Location) -> usize {
+        let line_start = self.starts[(location.line as usize).saturating_sub(1)];
+        self.sql[line_start..]
+            .char_indices()
+            .nth((location.column as usize).saturating_sub(1))
+            .map_or(self.sql.len(), |(i, _)| line_start + i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named(value: Value) -> QueryParams {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_named_placeholders_are_numbered_once() {
+        let bound = bind_params(
+            "SELECT * FROM eth_logs WHERE address = $address AND block_number > :block OR \"from\" = $address",
+            &named(json!({ "address": "0xabc", "block": {"type": "int4", "value": 10} })),
+            PlaceholderStyle::Numbered,
+        )
+        .unwrap();
+        assert_eq!(
+            bound.sql,
+            "SELECT * FROM eth_logs WHERE address = $1 AND block_number > $2 OR \"from\" = $1"
+        );
+        assert_eq!(
+            bound.values,
+            vec![ParamValue::Text("0xabc".into()), ParamValue::Int(10)]
+        );
+    }
+
+    #[test]
+    fn test_question_marks_bind_per_occurrence() {
+        let bound = bind_params(
+            "SELECT :a, :b, :a",
+            &named(json!({ "a": 1, "b": true })),
+            PlaceholderStyle::QuestionMark,
+        )
+        .unwrap();
+        assert_eq!(bound.sql, "SELECT ?, ?, ?");
+        assert_eq!(
+            bound.values,
+            vec![
+                ParamValue::BigInt(1),
+                ParamValue::Bool(true),
+                ParamValue::BigInt(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_positional_placeholders() {
+        let bound = bind_params(
+            "SELECT * FROM t WHERE a = $2 AND b = $1",
+            &named(json!(["x", {"type": "bigint", "value": "9007199254740993"}])),
+            PlaceholderStyle::Numbered,
+        )
+        .unwrap();
+        assert_eq!(bound.sql, "SELECT * FROM t WHERE a = $1 AND b = $2");
+        assert_eq!(
+            bound.values,
+            vec![
+                ParamValue::BigInt(9_007_199_254_740_993),
+                ParamValue::Text("x".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_placeholder_lookalikes_are_left_alone() {
+        let sql = "SELECT ':a', $$ $b $$, x::text, arr[lo:hi], \"$c\" FROM t";
+        let bound = bind_params(sql, &QueryParams::default(), PlaceholderStyle::Numbered).unwrap();
+        assert_eq!(bound.sql, sql);
+    }
+
+    #[test]
+    fn test_missing_and_unused_parameters_are_rejected() {
+        let style = PlaceholderStyle::Numbered;
+        assert_eq!(
+            bind_params("SELECT $a, $b", &named(json!({ "a": 1 })), style),
+            Err(ParamError::Missing("b".into()))
+        );
+        assert_eq!(
+            bind_params("SELECT $a", &named(json!({ "a": 1, "z": 2 })), style),
+            Err(ParamError::Unused("z".into()))
+        );
+        assert_eq!(
+            bind_params("SELECT $1", &named(json!([1, 2])), style),
+            Err(ParamError::Unused("$2".into()))
+        );
+        assert_eq!(
+            bind_params("SELECT 1", &named(json!({ "a": 1 })), style),
+            Err(ParamError::Unused("a".into()))
+        );
+        assert_eq!(
+            bind_params("SELECT $1, $a", &named(json!({ "a": 1 })), style),
+            Err(ParamError::Mixed)
+        );
+    }
+
+    #[test]
+    fn test_values_are_checked_against_declared_types() {
+        let style = PlaceholderStyle::Numbered;
+        let err = bind_params(
+            "SELECT $a",
+            &named(json!({ "a": {"type": "int2", "value": 70000} })),
+            style,
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), "invalid_parameter");
+        assert!(bind_params(
+            "SELECT $a",
+            &named(json!({ "a": {"type": "uuid", "value": "x"} })),
+            style
+        )
+        .is_err());
+        assert!(bind_params(
+            "SELECT $a",
+            &named(json!({ "a": {"type": "text", "value": 1} })),
+            style
+        )
+        .is_err());
+        assert!(bind_params("SELECT $a", &named(json!({ "a": null })), style).is_err());
+        assert_eq!(
+            bind_params(
+                "SELECT $a",
+                &named(json!({ "a": {"type": "text", "value": null} })),
+                style
+            )
+            .unwrap()
+            .values,
+            vec![ParamValue::Null(ParamType::Text)]
+        );
+    }
+}
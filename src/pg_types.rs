@@ -0,0 +1,225 @@
+use std::fmt::Write;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use serde_json::Value;
+
+/// Parses the text form of a Postgres array, such as `{1,2,NULL}` or `{{"a b",c},{d,e}}`, into
+/// nested JSON arrays. Unquoted `NULL` becomes `null`; every other element goes through
+/// `element`. Returns `None` when `text` is not an array literal.
+pub fn parse_array(text: &str, mut element: impl FnMut(&str) -> Value) -> Option<Value> {
+    // Arrays whose lower bound is not 1 carry their dimensions first: `[0:2]={1,2,3}`.
+    let text = match text.split_once('=') {
+        Some((dims, rest)) if dims.starts_with('[') => rest,
+        _ => text,
+    };
+    let mut chars = text.trim().chars().peekable();
+    let value = parse_level(&mut chars, &mut element)?;
+    chars.next().is_none().then_some(value)
+}
+
+fn parse_level(
+    chars: &mut Peekable<Chars<'_>>,
+    element: &mut impl FnMut(&str) -> Value,
+) -> Option<Value> {
+    if chars.next()? != '{' {
+        return None;
+    }
+    let mut items = Vec::new();
+    skip_whitespace(chars);
+    if chars.next_if_eq(&'}').is_some() {
+        return Some(Value::Array(items));
+    }
+    loop {
+        skip_whitespace(chars);
+        match chars.peek()? {
+            '{' => items.push(parse_level(chars, element)?),
+            '"' => {
+                chars.next();
+                let mut item = String::new();
+                loop {
+                    match chars.next()? {
+                        '\\' => item.push(chars.next()?),
+                        '"' => break,
+                        c => item.push(c),
+                    }
+                }
+                items.push(element(&item));
+            }
+            _ => {
+                let mut item = String::new();
+                while let Some(c) = chars.next_if(|&c| c != ',' && c != '}') {
+                    item.push(c);
+                }
+                let item = item.trim();
+                if item.eq_ignore_ascii_case("NULL") {
+                    items.push(Value::Null);
+                } else {
+                    items.push(element(item));
+                }
+            }
+        }
+        skip_whitespace(chars);
+        match chars.next()? {
+            ',' => continue,
+            '}' => return Some(Value::Array(items)),
+            _ => return None,
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars<'_>>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+/// Converts an interval in Postgres' default output style (`1 year 2 mons 3 days 04:05:06.5`)
+/// to the ISO-8601 duration `IntervalStyle = iso_8601` would print (`P1Y2M3DT4H5M6.5S`).
+/// Input that is already ISO-8601 is returned as is; other styles give `None`.
+pub fn interval_to_iso8601(text: &str) -> Option<String> {
+    let text = text.trim();
+    if text.starts_with('P') {
+        return Some(text.to_string());
+    }
+
+    let (mut years, mut months, mut days) = (0i64, 0i64, 0i64);
+    let mut time = None;
+    let mut tokens = text.split_whitespace();
+    while let Some(token) = tokens.next() {
+        if token.contains(':') {
+            time = Some(parse_time(token)?);
+            continue;
+        }
+        let amount: i64 = token.parse().ok()?;
+        match tokens.next()? {
+            "year" | "years" => years += amount,
+            "mon" | "mons" => months += amount,
+            "day" | "days" => days += amount,
+            _ => return None,
+        }
+    }
+
+    let mut iso = String::from("P");
+    for (amount, unit) in [(years, 'Y'), (months, 'M'), (days, 'D')] {
+        if amount != 0 {
+            let _ = write!(iso, "{amount}{unit}");
+        }
+    }
+    if let Some((sign, hours, minutes, seconds)) = time {
+        let mut parts = String::new();
+        if hours != 0 {
+            let _ = write!(parts, "{sign}{hours}H");
+        }
+        if minutes != 0 {
+            let _ = write!(parts, "{sign}{minutes}M");
+        }
+        if seconds != "0" {
+            let _ = write!(parts, "{sign}{seconds}S");
+        }
+        if !parts.is_empty() {
+            iso.push('T');
+            iso.push_str(&parts);
+        }
+    }
+    if iso == "P" {
+        iso.push_str("T0S");
+    }
+    Some(iso)
+}
+
+/// Splits `[+-]HH:MM:SS[.ffffff]` into a sign prefix, hours, minutes and the seconds with
+/// leading and trailing zeros dropped.
+fn parse_time(token: &str) -> Option<(&'static str, u64, u64, String)> {
+    let (sign, token) = match token.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", token.strip_prefix('+').unwrap_or(token)),
+    };
+    let mut fields = token.split(':');
+    let hours = fields.next()?.parse().ok()?;
+    let minutes = fields.next()?.parse().ok()?;
+    let (whole, fraction) = match fields.next() {
+        Some(seconds) => seconds.split_once('.').unwrap_or((seconds, "")),
+        None => ("0", ""),
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    let mut seconds = whole.parse::<u64>().ok()?.to_string();
+    let fraction = fraction.trim_end_matches('0');
+    if !fraction.is_empty() {
+        if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        seconds.push('.');
+        seconds.push_str(fraction);
+    }
+    Some((sign, hours, minutes, seconds))
+}
+
+/// Formats a UUID received as its 16 raw bytes.
+pub fn uuid_from_bytes(bytes: &[u8]) -> Option<String> {
+    if bytes.len() != 16 {
+        return None;
+    }
+    let mut uuid = String::with_capacity(36);
+    for (i, byte) in bytes.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            uuid.push('-');
+        }
+        let _ = write!(uuid, "{byte:02x}");
+    }
+    Some(uuid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(text: &str) -> Option<Value> {
+        parse_array(text, |s| Value::String(s.to_string()))
+    }
+
+    #[test]
+    fn test_parse_array() {
+        assert_eq!(parse("{}"), Some(json!([])));
+        assert_eq!(parse("{1,2,NULL}"), Some(json!(["1", "2", null])));
+        assert_eq!(
+            parse(r#"{"a b",NULL,"NULL","c\"d","e\\f", g }"#),
+            Some(json!(["a b", null, "NULL", "c\"d", "e\\f", "g"]))
+        );
+        assert_eq!(
+            parse("{{1,2},{3,4}}"),
+            Some(json!([["1", "2"], ["3", "4"]]))
+        );
+        assert_eq!(parse("[0:1]={5,6}"), Some(json!(["5", "6"])));
+        assert_eq!(parse("1 year"), None);
+        assert_eq!(parse("{1,2"), None);
+        assert_eq!(parse("{1,2}x"), None);
+    }
+
+    #[test]
+    fn test_interval_to_iso8601() {
+        let iso = |text| interval_to_iso8601(text).unwrap();
+        assert_eq!(iso("1 year 2 mons 3 days"), "P1Y2M3D");
+        assert_eq!(iso("04:00:00"), "PT4H");
+        assert_eq!(iso("1 day 12:00:00"), "P1DT12H");
+        assert_eq!(iso("-1 days +02:03:04.500"), "P-1DT2H3M4.5S");
+        assert_eq!(iso("-00:00:01.25"), "PT-1.25S");
+        assert_eq!(iso("00:00:00"), "PT0S");
+        assert_eq!(iso("P1Y2M"), "P1Y2M");
+        assert_eq!(interval_to_iso8601("@ 1 year ago"), None);
+    }
+
+    #[test]
+    fn test_uuid_from_bytes() {
+        let bytes = [
+            0xa0, 0xee, 0xbc, 0x99, 0x9c, 0x0b, 0x4e, 0xf8, 0xbb, 0x6d, 0x6b, 0xb9, 0xbd, 0x38,
+            0x0a, 0x11,
+        ];
+        assert_eq!(
+            uuid_from_bytes(&bytes).unwrap(),
+            "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11"
+        );
+        assert_eq!(uuid_from_bytes(&bytes[..4]), None);
+    }
+}
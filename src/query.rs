@@ -0,0 +1,739 @@
+use std::future::Future;
+use std::time::Duration;
+
+use eql_core::{
+    common::query_result::QueryResult as EqlQueryResult, interpreter::Interpreter as EQlInterpreter,
+};
+use futures::{stream, StreamExt};
+use rocket::{
+    http::{Header, Status},
+    response::{content::RawJson, status},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::any::{AnyKind, AnyPool, AnyRow};
+use sui_ql_core::{
+    common::query_result::QueryResult as SuiQueryResult,
+    interpreter::Interpreter as SuiQlInterpreter,
+};
+use tokio::time::timeout;
+
+use crate::arrow_export;
+use crate::auth::{self, KeyPolicy};
+use crate::chains::{self, ChainRegistry, Engine};
+use crate::config::ServerConfig;
+use crate::csv_export;
+use crate::indexed::{self, ExecError};
+use crate::params::{self, BoundQuery, QueryParams};
+use crate::response::QueryResponse;
+use crate::result_cache::{CacheMode, ResultCache};
+use crate::sql_guard;
+use crate::sql_rewrite;
+use crate::sql_to_json::{
+    columns_meta, row_to_json_array, row_to_json_with, stringify_unsafe_integers, BinaryEncoding,
+    DecodeWarning, JsonOptions, NumericMode,
+};
+use crate::utils::{self, json_error, json_response, timeout_error};
+
+#[derive(Serialize)]
+#[serde(tag = "type", content = "data")]
+pub enum QueryResult {
+    Sui(Vec<SuiQueryResult>),
+    Eql(Vec<EqlQueryResult>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryType {
+    Rpc,
+    Indexed,
+}
+
+/// How rows are sent back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    /// One JSON document holding every row, capped at the server's row limit.
+    #[default]
+    Json,
+    /// One JSON object per line, streamed as rows are read and not subject to the server's row
+    /// limit. A key's own row cap still holds; when it cuts the result off, the stream ends
+    /// with a `{"truncated": true, "row_limit": ...}` line. Indexed queries only.
+    Ndjson,
+    /// A CSV download with a header row.
+    Csv,
+    /// An Arrow IPC stream download with typed columns. Indexed queries only.
+    Arrow,
+    /// A Parquet file download with typed columns. Indexed queries only.
+    Parquet,
+}
+
+impl ResponseFormat {
+    fn name(self) -> &'static str {
+        match self {
+            ResponseFormat::Json => "json",
+            ResponseFormat::Ndjson => "ndjson",
+            ResponseFormat::Csv => "csv",
+            ResponseFormat::Arrow => "arrow",
+            ResponseFormat::Parquet => "parquet",
+        }
+    }
+
+    fn supports_rpc(self) -> bool {
+        matches!(self, ResponseFormat::Json | ResponseFormat::Csv)
+    }
+
+    /// Whether rows are sent back as a file rather than in the JSON envelope. Downloads are
+    /// capped like `json`, with `X-Truncated` set when rows were dropped.
+    fn is_download(self) -> bool {
+        matches!(
+            self,
+            ResponseFormat::Csv | ResponseFormat::Arrow | ResponseFormat::Parquet
+        )
+    }
+}
+
+/// How each indexed row is encoded in `json` and `ndjson` responses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RowShape {
+    /// `{"column": value}`. Repeated column names are merged into one array value.
+    #[default]
+    Object,
+    /// `[value, ...]` in the order of the `columns` block, with no merging.
+    Array,
+}
+
+impl RowShape {
+    /// Encodes the row at `index`, adding a warning for each cell that failed to decode.
+    fn encode(
+        self,
+        row: &AnyRow,
+        index: usize,
+        options: &JsonOptions,
+        warnings: &mut Vec<DecodeWarning>,
+    ) -> Value {
+        let mut errors = Vec::new();
+        let value = match self {
+            RowShape::Object => row_to_json_with(row, options, &mut errors),
+            RowShape::Array => row_to_json_array(row, options, &mut errors),
+        };
+        warnings.extend(
+            errors
+                .into_iter()
+                .map(|error| DecodeWarning { row: index, error }),
+        );
+        value
+    }
+}
+
+/// The interpreter a query runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum QueryEngine {
+    /// The RPC engine of a chain.
+    Rpc(Engine),
+    /// SQL against the indexed database.
+    Sql,
+}
+
+impl QueryEngine {
+    pub fn name(self) -> &'static str {
+        match self {
+            QueryEngine::Rpc(engine) => engine.name(),
+            QueryEngine::Sql => "sql",
+        }
+    }
+}
+
+impl TryFrom<String> for QueryEngine {
+    type Error = serde::de::value::Error;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        [
+            QueryEngine::Rpc(Engine::Eql),
+            QueryEngine::Rpc(Engine::SuiQl),
+            QueryEngine::Sql,
+        ]
+        .into_iter()
+        .find(|engine| engine.name() == name)
+        .ok_or_else(|| serde::de::Error::unknown_variant(&name, &["eql", "suiql", "sql"]))
+    }
+}
+
+/// Body of `POST /v1/query`, e.g. `{"type": "indexed", "query": "SELECT ..."}`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueryRequest {
+    /// May be left out when `engine` is given.
+    #[serde(rename = "type", default)]
+    pub query_type: Option<QueryType>,
+    /// Picks the interpreter instead of detecting it from the chains an RPC query targets.
+    #[serde(default)]
+    pub engine: Option<QueryEngine>,
+    pub query: String,
+    #[serde(default)]
+    pub params: QueryParams,
+    #[serde(default)]
+    pub options: QueryOptions,
+}
+
+/// Per-request knobs. Unknown options are rejected rather than silently ignored.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueryOptions {
+    /// Cancels the query after this many milliseconds, capped by the server maximum. Applies to
+    /// both indexed and RPC queries.
+    pub timeout_ms: Option<u64>,
+    pub format: ResponseFormat,
+    pub row_shape: RowShape,
+    /// How NUMERIC and DECIMAL values are written: `auto`, `string` or `number`.
+    pub numeric: NumericMode,
+    /// Writes integers beyond JavaScript's safe range as strings, in indexed rows and RPC
+    /// results alike.
+    pub bigint_as_string: bool,
+    /// How binary columns are written: `hex` (`0x…`) or `base64`.
+    pub binary: BinaryEncoding,
+    /// Fails the request when a value cannot be decoded. Otherwise the value is written as null
+    /// and reported under `warnings`.
+    pub strict: bool,
+    /// `bypass` runs the query even when its result is cached.
+    pub cache: CacheMode,
+}
+
+impl QueryOptions {
+    fn json_options(&self) -> JsonOptions {
+        JsonOptions {
+            numeric: self.numeric,
+            bigint_as_string: self.bigint_as_string,
+            binary: self.binary,
+        }
+    }
+}
+
+impl QueryRequest {
+    /// The engine to run on: `engine` when given, otherwise SQL for indexed queries and, for
+    /// RPC queries, the engine of the chains named after `ON`.
+    fn resolve_engine(&self, chains: &ChainRegistry) -> Result<QueryEngine, String> {
+        match (self.query_type, self.engine) {
+            (None, None) => Err(
+                "Set type (\"rpc\" or \"indexed\") or engine (\"eql\", \"suiql\" or \"sql\")."
+                    .to_string(),
+            ),
+            (Some(QueryType::Indexed) | None, Some(QueryEngine::Sql))
+            | (Some(QueryType::Indexed), None) => Ok(QueryEngine::Sql),
+            (Some(QueryType::Rpc) | None, Some(engine @ QueryEngine::Rpc(_))) => Ok(engine),
+            (Some(QueryType::Rpc), None) => chains
+                .rpc_engine(&utils::remove_sql_comments(&self.query))
+                .map(QueryEngine::Rpc),
+            (Some(query_type), Some(engine)) => Err(format!(
+                "The {} engine does not run {} queries.",
+                engine.name(),
+                match query_type {
+                    QueryType::Rpc => "rpc",
+                    QueryType::Indexed => "indexed",
+                }
+            )),
+        }
+    }
+}
+
+/// Runs a query on the engine picked by [`QueryRequest::resolve_engine`], within what the
+/// caller's key allows.
+pub async fn run(
+    request: QueryRequest,
+    policy: &KeyPolicy,
+    pool: &AnyPool,
+    cache: &ResultCache,
+    config: &ServerConfig,
+) -> QueryResponse {
+    let engine = match request.resolve_engine(&config.chains) {
+        Ok(engine) => engine,
+        Err(e) => return json_response(Status::BadRequest, json!({ "error": e })).into(),
+    };
+    if !policy.allows_engine(engine) {
+        return json_response(
+            Status::Forbidden,
+            json!({
+                "error": format!("This API key may not run {} queries.", engine.name()),
+                "code": "engine_not_allowed",
+            }),
+        )
+        .into();
+    }
+    let rpc_engine = match engine {
+        QueryEngine::Rpc(engine) => engine,
+        QueryEngine::Sql => {
+            return run_indexed(
+                &request.query,
+                &request.params,
+                &request.options,
+                policy,
+                pool,
+                cache,
+                config,
+            )
+            .await
+        }
+    };
+    if !request.params.is_empty() {
+        return json_response(
+            Status::BadRequest,
+            json!({ "error": "Query parameters are only supported for indexed queries." }),
+        )
+        .into();
+    }
+    if !request.options.format.supports_rpc() {
+        return json_response(
+            Status::BadRequest,
+            json!({
+                "error": format!(
+                    "The {} format is only supported for indexed queries.",
+                    request.options.format.name()
+                )
+            }),
+        )
+        .into();
+    }
+    let targets: Vec<String> = chains::target_chains(&utils::remove_sql_comments(&request.query))
+        .into_iter()
+        .map(|name| auth::canonical_chain(&config.chains, name))
+        .collect();
+    if let Some(chain) = policy.denied_chain(targets.iter().map(String::as_str)) {
+        return chain_not_allowed(chain).into();
+    }
+    // Without ON the interpreter picks a chain itself, which the key may not be allowed on.
+    if targets.is_empty() && policy.chains.is_some() {
+        return json_response(
+            Status::Forbidden,
+            json!({
+                "error": "This API key may only query some chains. Name them after ON.",
+                "code": "chain_required",
+            }),
+        )
+        .into();
+    }
+    run_rpc(
+        rpc_engine,
+        &request.query,
+        &request.options,
+        policy,
+        cache,
+        config,
+    )
+    .await
+}
+
+fn chain_not_allowed(chain: &str) -> status::Custom<RawJson<String>> {
+    json_response(
+        Status::Forbidden,
+        json!({
+            "error": format!("This API key may not query {chain}."),
+            "code": "chain_not_allowed",
+        }),
+    )
+}
+
+async fn run_rpc(
+    engine: Engine,
+    query: &str,
+    options: &QueryOptions,
+    policy: &KeyPolicy,
+    cache: &ResultCache,
+    config: &ServerConfig,
+) -> QueryResponse {
+    let query = &utils::remove_sql_comments(query);
+    // Results for pinned blocks never change; the rest are good for about a block.
+    let ttl = (!config.chains.is_pinned(query))
+        .then(|| config.chains.latest_ttl(query, config.cache.latest_ttl));
+    let shape = format!("{:?}", (options.format, options.bigint_as_string));
+    let key = ResultCache::key(&[engine.name(), &utils::collapse_whitespace(query), &shape]);
+    if options.cache == CacheMode::Use {
+        if let Some(cached) = cache.get(&key) {
+            return cached;
+        }
+    }
+    let deadline = policy.cap_timeout(config.rpc_timeout(options.timeout_ms));
+    let response = call_interpreter(engine, query, options, deadline).await;
+    cache.store_for(key, response, ttl)
+}
+
+/// Runs an RPC query on its interpreter and renders the result.
+async fn call_interpreter(
+    engine: Engine,
+    query: &str,
+    options: &QueryOptions,
+    deadline: Duration,
+) -> QueryResponse {
+    match engine {
+        Engine::SuiQl => {
+            let run = async {
+                SuiQlInterpreter::run_program(query)
+                    .await
+                    .map(QueryResult::Sui)
+            };
+            run_with_deadline("SUI_QL", run, options, deadline).await
+        }
+        Engine::Eql => {
+            let run = async {
+                EQlInterpreter::run_program(query)
+                    .await
+                    .map(QueryResult::Eql)
+            };
+            run_with_deadline("EQL", run, options, deadline).await
+        }
+    }
+}
+
+/// Awaits an interpreter run for at most `deadline` and renders its result. Dropping the run
+/// on timeout discards whatever it had collected so far.
+async fn run_with_deadline<E: ToString>(
+    label: &str,
+    run: impl Future<Output = Result<QueryResult, E>>,
+    options: &QueryOptions,
+    deadline: Duration,
+) -> QueryResponse {
+    match timeout(deadline, run).await {
+        Ok(Ok(data)) if options.format == ResponseFormat::Csv => {
+            match serde_json::to_value(&data) {
+                Ok(value) => QueryResponse::csv(csv_export::tables_to_csv(&value), label),
+                Err(err) => json_error(err).into(),
+            }
+        }
+        Ok(Ok(data)) => {
+            let json = if options.bigint_as_string {
+                serde_json::to_value(&data).map(|mut value| {
+                    stringify_unsafe_integers(&mut value);
+                    value.to_string()
+                })
+            } else {
+                serde_json::to_string(&data)
+            };
+            match json {
+                Ok(json) => status::Custom(Status::Ok, RawJson(json)).into(),
+                Err(err) => json_error(err).into(),
+            }
+        }
+        Ok(Err(err)) => json_error(err).into(),
+        Err(_elapsed) => timeout_error(label, deadline).into(),
+    }
+}
+
+async fn run_indexed(
+    query: &str,
+    params: &QueryParams,
+    options: &QueryOptions,
+    policy: &KeyPolicy,
+    pool: &AnyPool,
+    cache: &ResultCache,
+    config: &ServerConfig,
+) -> QueryResponse {
+    // The guard reads comments itself, so error positions point into the submitted text.
+    let mut ast = match sql_guard::validate_read_only(query, &config.guard) {
+        Ok(ast) => ast,
+        Err(e) => return json_response(Status::BadRequest, e.to_json()).into(),
+    };
+    let row_limit = policy.row_limit(config.max_rows);
+    // MSSQL spells row limits TOP or FETCH rather than LIMIT; there the cap is only applied
+    // while reading. A key's own row cap holds for streamed results too.
+    let limit_in_sql = pool.any_kind() != AnyKind::Mssql
+        && (options.format != ResponseFormat::Ndjson || policy.max_rows.is_some());
+    if limit_in_sql {
+        sql_rewrite::apply_row_limit(&mut ast, row_limit);
+    }
+
+    sql_rewrite::map_chain_tables(&mut ast, &config.chains);
+    let referenced = sql_rewrite::referenced_chains(&ast, &config.chains);
+    if let Some(chain) = policy.denied_chain(referenced.iter().map(String::as_str)) {
+        return chain_not_allowed(chain).into();
+    }
+
+    let rewritten_query = ast.to_string();
+    if let Err(e) = gluesql::prelude::parse(&rewritten_query) {
+        return json_error(e).into();
+    }
+
+    let bound = match params::bind_params(&rewritten_query, params, pool.any_kind().into()) {
+        Ok(bound) => bound,
+        Err(e) => return json_response(Status::BadRequest, e.to_json()).into(),
+    };
+
+    let timeout = policy.cap_timeout(config.query_timeout(options.timeout_ms));
+    if options.format == ResponseFormat::Ndjson {
+        let row_limit = policy.max_rows.is_some().then_some(row_limit);
+        return stream_ndjson(pool, bound, timeout, row_limit, options).await;
+    }
+
+    // The rewritten query has comments, chain names and whitespace normalized already.
+    let shape = format!(
+        "{:?}",
+        (
+            options.format,
+            options.row_shape,
+            options.numeric,
+            options.bigint_as_string,
+            options.binary,
+            options.strict,
+            row_limit,
+        )
+    );
+    let key = ResultCache::key(&["sql", &rewritten_query, &format!("{params:?}"), &shape]);
+    if options.cache == CacheMode::Use {
+        if let Some(cached) = cache.get(&key) {
+            return cached;
+        }
+    }
+    let response = fetch_indexed(pool, &bound, timeout, row_limit, options).await;
+    cache.store(key, response)
+}
+
+/// Runs an indexed query and renders its rows in the requested, non-streamed format.
+async fn fetch_indexed(
+    pool: &AnyPool,
+    bound: &BoundQuery,
+    timeout: Duration,
+    row_limit: usize,
+    options: &QueryOptions,
+) -> QueryResponse {
+    let fetched = match indexed::fetch_all(pool, bound, timeout, row_limit).await {
+        Ok(fetched) => fetched,
+        Err(e) => return exec_error(e).into(),
+    };
+    let mut warnings = Vec::new();
+    if options.format.is_download() {
+        let json_options = options.json_options();
+        let response = match export_rows(options.format, &fetched, &json_options, &mut warnings) {
+            Ok(response) => response,
+            Err(e) => return json_error(e).into(),
+        };
+        if let (true, Some(warning)) = (options.strict, warnings.first()) {
+            return decode_failure(warning).into();
+        }
+        let mut response =
+            response.with_header(Header::new("X-Row-Count", fetched.rows.len().to_string()));
+        if !warnings.is_empty() {
+            response =
+                response.with_header(Header::new("X-Decode-Warnings", warnings.len().to_string()));
+        }
+        if fetched.truncated {
+            response = response
+                .with_header(Header::new("X-Truncated", "true"))
+                .with_header(Header::new("X-Row-Limit", row_limit.to_string()));
+        }
+        return response;
+    }
+    let json_options = options.json_options();
+    let columns = columns_meta(&fetched.rows, fetched.describe.as_ref(), &json_options);
+    let rows_json: Vec<Value> = fetched
+        .rows
+        .iter()
+        .enumerate()
+        .map(|(index, row)| {
+            options
+                .row_shape
+                .encode(row, index, &json_options, &mut warnings)
+        })
+        .collect();
+    if let (true, Some(warning)) = (options.strict, warnings.first()) {
+        return decode_failure(warning).into();
+    }
+    let row_count = rows_json.len();
+
+    let wrapped_data: Vec<Value> = rows_json.into_iter().map(|row| json!(row)).collect();
+
+    status::Custom(
+        Status::Ok,
+        RawJson(
+            json!({
+                "type": "Wql",
+                "data": [
+                    {
+                        "result": {
+                            "indexed": wrapped_data
+                        }
+                    }
+                ],
+                "columns": columns,
+                "truncated": fetched.truncated,
+                "row_limit": row_limit,
+                "row_count": row_count,
+                "warnings": warnings
+            })
+            .to_string(),
+        ),
+    )
+    .into()
+}
+
+/// Streams rows as newline-delimited JSON. The first row is awaited before answering, so a
+/// query that fails up front still gets an error status; a failure after rows have gone out
+/// ends the stream with an `{"error": ...}` line instead. A cell that fails to decode is
+/// followed by a `{"warning": ...}` line, or under `strict` ends the stream like an error.
+/// Rows past `row_limit` are replaced by a single `{"truncated": true, ...}` line.
+async fn stream_ndjson(
+    pool: &AnyPool,
+    bound: BoundQuery,
+    timeout: Duration,
+    row_limit: Option<usize>,
+    options: &QueryOptions,
+) -> QueryResponse {
+    let row_shape = options.row_shape;
+    let json_options = options.json_options();
+    let strict = options.strict;
+    // The lines for one row, or the response that ends the stream in its place.
+    let encode = move |index: usize,
+                       row: Result<AnyRow, ExecError>|
+          -> Result<String, status::Custom<RawJson<String>>> {
+        let row = row.map_err(exec_error)?;
+        let mut warnings = Vec::new();
+        let mut lines = row_shape
+            .encode(&row, index, &json_options, &mut warnings)
+            .to_string();
+        lines.push('\n');
+        if let (true, Some(warning)) = (strict, warnings.first()) {
+            return Err(decode_failure(warning));
+        }
+        for warning in warnings {
+            lines.push_str(&json!({ "warning": warning }).to_string());
+            lines.push('\n');
+        }
+        Ok(lines)
+    };
+
+    let mut rows = indexed::stream(pool.clone(), bound, timeout, row_limit);
+    let first = match rows.recv().await {
+        Some(row) => match encode(0, row) {
+            Ok(lines) => Some(Ok(lines)),
+            Err(failure) => return failure.into(),
+        },
+        None => None,
+    };
+    let rest = stream::unfold(rows, |mut rows| async move {
+        rows.recv().await.map(|row| (row, rows))
+    })
+    .enumerate()
+    .map(move |(index, row)| encode(index + 1, row));
+
+    // An error line ends the stream, as does the truncation marker in place of the row past
+    // the cap; dropping the receiver cancels the query.
+    let lines = stream::iter(first).chain(rest).enumerate();
+    let lines = lines.scan(false, move |ended, (index, lines)| {
+        let next = match (lines, row_limit) {
+            _ if *ended => None,
+            (_, Some(row_limit)) if index >= row_limit => {
+                *ended = true;
+                let marker = json!({ "truncated": true, "row_limit": row_limit });
+                Some(format!("{marker}\n"))
+            }
+            (Ok(lines), _) => Some(lines),
+            (Err(status::Custom(_, RawJson(mut body))), _) => {
+                *ended = true;
+                body.push('\n');
+                Some(body)
+            }
+        };
+        futures::future::ready(next)
+    });
+    QueryResponse::ndjson(lines.boxed())
+}
+
+/// Renders rows as one of the download formats.
+fn export_rows(
+    format: ResponseFormat,
+    fetched: &indexed::Fetched,
+    options: &JsonOptions,
+    warnings: &mut Vec<DecodeWarning>,
+) -> Result<QueryResponse, String> {
+    let (rows, describe) = (fetched.rows.as_slice(), fetched.describe.as_ref());
+    let response = match format {
+        ResponseFormat::Csv => {
+            let csv = csv_export::rows_to_csv(rows, describe, options, warnings);
+            QueryResponse::csv(csv, "indexed")
+        }
+        ResponseFormat::Arrow => {
+            let batch =
+                arrow_export::rows_to_batch(rows, describe, warnings).map_err(|e| e.to_string())?;
+            let body = arrow_export::to_ipc(&batch).map_err(|e| e.to_string())?;
+            QueryResponse::arrow(body, "indexed")
+        }
+        ResponseFormat::Parquet => {
+            let batch =
+                arrow_export::rows_to_batch(rows, describe, warnings).map_err(|e| e.to_string())?;
+            let body = arrow_export::to_parquet(&batch).map_err(|e| e.to_string())?;
+            QueryResponse::parquet(body, "indexed")
+        }
+        ResponseFormat::Json | ResponseFormat::Ndjson => {
+            return Err(format!("{} is not a download format", format.name()))
+        }
+    };
+    Ok(response)
+}
+
+/// The response for a value that could not be decoded under `strict`.
+fn decode_failure(warning: &DecodeWarning) -> status::Custom<RawJson<String>> {
+    json_response(
+        Status::UnprocessableEntity,
+        json!({ "error": warning.to_string(), "warning": warning }),
+    )
+}
+
+fn exec_error(e: ExecError) -> status::Custom<RawJson<String>> {
+    match e {
+        ExecError::Timeout(timeout) => timeout_error("indexed", timeout),
+        e => json_error(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(request: Value) -> Result<QueryEngine, String> {
+        let request: QueryRequest = serde_json::from_value(request).unwrap();
+        request.resolve_engine(&ChainRegistry::default())
+    }
+
+    #[test]
+    fn test_resolve_engine() {
+        let get = "GET * FROM account 0x1 ON eth WHERE memo = 'sui'";
+        assert_eq!(
+            resolve(json!({"type": "rpc", "query": get})),
+            Ok(QueryEngine::Rpc(Engine::Eql))
+        );
+        assert_eq!(
+            resolve(json!({"type": "rpc", "query": "GET * FROM object 0x2 ON sui"})),
+            Ok(QueryEngine::Rpc(Engine::SuiQl))
+        );
+        assert_eq!(
+            resolve(json!({"engine": "suiql", "query": get})),
+            Ok(QueryEngine::Rpc(Engine::SuiQl))
+        );
+        assert_eq!(
+            resolve(json!({"engine": "sql", "query": "SELECT 1"})),
+            Ok(QueryEngine::Sql)
+        );
+        assert_eq!(
+            resolve(json!({"type": "indexed", "query": "SELECT 1"})),
+            Ok(QueryEngine::Sql)
+        );
+        assert!(resolve(json!({"type": "indexed", "engine": "eql", "query": get})).is_err());
+        assert!(resolve(json!({"query": get})).is_err());
+        let unknown = json!({"engine": "graphql", "query": get});
+        assert!(serde_json::from_value::<QueryRequest>(unknown).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_interpreter_deadline() {
+        let run = std::future::pending::<Result<QueryResult, String>>();
+        let deadline = Duration::from_millis(20);
+        let response = run_with_deadline("EQL", run, &QueryOptions::default(), deadline).await;
+        let QueryResponse::Json { response, .. } = response else {
+            panic!("expected a JSON error");
+        };
+        assert_eq!(response.0, Status::GatewayTimeout);
+        let body: Value = serde_json::from_str(&response.1 .0).unwrap();
+        assert_eq!(body["code"], "query_timeout");
+        assert_eq!(body["engine"], "EQL");
+        assert_eq!(body["timeout_ms"], 20);
+    }
+}
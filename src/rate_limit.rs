@@ -0,0 +1,504 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{Header, Status},
+    request::{FromRequest, Outcome},
+    response::{content::RawJson, status},
+    Request, Response,
+};
+use serde_json::json;
+
+use crate::auth::{self, ApiKey};
+use crate::config::{env_list, env_parse};
+use crate::utils::json_response;
+
+/// Beyond this many tracked clients, the one seen least recently without queries running is
+/// forgotten.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Per-client limits on the query routes. A client is an API key, or the caller's IP address
+/// when keys are not required.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// Queries a client may start per minute, refilled continuously
+    /// (`RATE_LIMIT_PER_MINUTE`). 0 turns the rate limit off.
+    pub per_minute: u32,
+    /// Queries a client may start back to back before it is held to `per_minute`
+    /// (`RATE_LIMIT_BURST`).
+    pub burst: u32,
+    /// Queries a client may have running at once, streamed results included
+    /// (`MAX_CONCURRENT_QUERIES`). 0 for no limit.
+    pub max_concurrent: usize,
+    /// Reverse proxies whose `X-Real-IP` header is believed (`TRUSTED_PROXIES`). Requests from
+    /// anywhere else count against their peer address, so the header cannot be used to pose as
+    /// a fresh client.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_minute: 60,
+            burst: 20,
+            max_concurrent: 4,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            per_minute: env_parse("RATE_LIMIT_PER_MINUTE", defaults.per_minute),
+            burst: env_parse("RATE_LIMIT_BURST", defaults.burst).max(1),
+            max_concurrent: env_parse("MAX_CONCURRENT_QUERIES", defaults.max_concurrent),
+            trusted_proxies: env_list("TRUSTED_PROXIES")
+                .into_iter()
+                .filter_map(|ip| match ip.parse() {
+                    Ok(ip) => Some(ip),
+                    Err(_) => {
+                        log::warn!("Ignoring invalid address {ip:?} in TRUSTED_PROXIES");
+                        None
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+struct Client {
+    tokens: f64,
+    refilled: Instant,
+    running: usize,
+}
+
+impl Client {
+    fn refill(&mut self, config: &RateLimitConfig, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * config.refill_per_sec()).min(f64::from(config.burst));
+        self.refilled = now;
+    }
+}
+
+/// The tracked clients, with the order to forget them in.
+#[derive(Default)]
+struct Clients {
+    map: HashMap<String, Client>,
+    /// Every tracked client once, with when it was last seen as of being queued, roughly least
+    /// recently seen first. A client seen since it was queued goes to the back instead of being
+    /// evicted, which keeps eviction O(1) amortized.
+    queue: VecDeque<(Instant, String)>,
+}
+
+impl Clients {
+    /// Forgets the least recently seen client that has no queries running, whose permits would
+    /// otherwise give their slots back to a fresh entry. Returns false when every client has
+    /// some running; there are no more of those than requests in flight.
+    fn evict_one(&mut self) -> bool {
+        let mut busy = 0;
+        while busy < self.queue.len() {
+            let Some((queued, client)) = self.queue.pop_front() else {
+                break;
+            };
+            match self.map.get(&client) {
+                Some(state) if state.refilled > queued => {
+                    self.queue.push_back((state.refilled, client));
+                }
+                Some(state) if state.running > 0 => {
+                    self.queue.push_back((queued, client));
+                    busy += 1;
+                }
+                Some(_) => {
+                    self.map.remove(&client);
+                    return true;
+                }
+                None => {}
+            }
+        }
+        false
+    }
+}
+
+/// Token buckets and running-query counts for every client, kept in Rocket's managed state.
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    clients: Arc<Mutex<Clients>>,
+    max_clients: usize,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            clients: Arc::default(),
+            max_clients: MAX_TRACKED_CLIENTS,
+        }
+    }
+
+    /// Takes a token from `client`'s bucket and a slot among its running queries. The slot is
+    /// given back when the returned permit is dropped.
+    pub fn acquire(&self, client: &str) -> Result<Permit, RateLimited> {
+        self.acquire_at(client, Instant::now())
+    }
+
+    fn acquire_at(&self, client: &str, now: Instant) -> Result<Permit, RateLimited> {
+        let config = &self.config;
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        let clients = &mut *clients;
+        if !clients.map.contains_key(client) {
+            while clients.map.len() >= self.max_clients.max(1) && clients.evict_one() {}
+            clients.queue.push_back((now, client.to_string()));
+        }
+        let state = clients
+            .map
+            .entry(client.to_string())
+            .or_insert_with(|| Client {
+                tokens: f64::from(config.burst),
+                refilled: now,
+                running: 0,
+            });
+        state.refill(config, now);
+
+        let rate_limited = config.per_minute > 0;
+        if config.max_concurrent > 0 && state.running >= config.max_concurrent {
+            return Err(RateLimited {
+                reason: LimitReason::Concurrency,
+                // Running queries give their slot back when they finish, which is not known up
+                // front; a second is a reasonable time to try again.
+                quota: self.quota(state, Some(Duration::from_secs(1))),
+            });
+        }
+        if rate_limited && state.tokens < 1.0 {
+            let wait = (1.0 - state.tokens) / config.refill_per_sec();
+            return Err(RateLimited {
+                reason: LimitReason::Rate,
+                quota: self.quota(state, Some(Duration::from_secs_f64(wait.ceil()))),
+            });
+        }
+
+        if rate_limited {
+            state.tokens -= 1.0;
+        }
+        state.running += 1;
+        Ok(Permit {
+            clients: Arc::clone(&self.clients),
+            client: client.to_string(),
+            quota: self.quota(state, None),
+        })
+    }
+
+    fn quota(&self, state: &Client, retry_after: Option<Duration>) -> Quota {
+        let config = &self.config;
+        let missing = f64::from(config.burst) - state.tokens;
+        Quota {
+            per_minute: config.per_minute,
+            remaining: state.tokens.max(0.0).floor() as u32,
+            reset: (config.per_minute > 0)
+                .then(|| Duration::from_secs_f64((missing / config.refill_per_sec()).ceil())),
+            max_concurrent: config.max_concurrent,
+            running: state.running,
+            retry_after,
+        }
+    }
+}
+
+/// A client's standing after its last request, sent back in `X-RateLimit-*` headers by
+/// [`RateLimitHeaders`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quota {
+    per_minute: u32,
+    /// Queries the client can start right away.
+    remaining: u32,
+    /// Time until the bucket is full again.
+    reset: Option<Duration>,
+    max_concurrent: usize,
+    running: usize,
+    retry_after: Option<Duration>,
+}
+
+impl Quota {
+    fn headers(&self) -> Vec<Header<'static>> {
+        let mut headers = Vec::new();
+        if let Some(reset) = self.reset {
+            headers.push(Header::new(
+                "X-RateLimit-Limit",
+                self.per_minute.to_string(),
+            ));
+            headers.push(Header::new(
+                "X-RateLimit-Remaining",
+                self.remaining.to_string(),
+            ));
+            headers.push(Header::new(
+                "X-RateLimit-Reset",
+                reset.as_secs().to_string(),
+            ));
+        }
+        if self.max_concurrent > 0 {
+            let free = self.max_concurrent.saturating_sub(self.running);
+            headers.push(Header::new(
+                "X-Concurrency-Limit",
+                self.max_concurrent.to_string(),
+            ));
+            headers.push(Header::new("X-Concurrency-Remaining", free.to_string()));
+        }
+        if let Some(retry_after) = self.retry_after {
+            headers.push(Header::new(
+                "Retry-After",
+                retry_after.as_secs().to_string(),
+            ));
+        }
+        headers
+    }
+}
+
+/// A slot among the client's running queries, held until the query is done. Streamed responses
+/// keep it until the stream ends (see
+/// [`QueryResponse::holding`](crate::response::QueryResponse::holding)).
+pub struct Permit {
+    clients: Arc<Mutex<Clients>>,
+    client: String,
+    quota: Quota,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(state) = clients.map.get_mut(&self.client) {
+            state.running = state.running.saturating_sub(1);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitReason {
+    Rate,
+    Concurrency,
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimited {
+    pub reason: LimitReason,
+    quota: Quota,
+}
+
+impl RateLimited {
+    pub fn response(&self) -> status::Custom<RawJson<String>> {
+        let retry_after = self.quota.retry_after.unwrap_or_default().as_secs();
+        let (error, code) = match self.reason {
+            LimitReason::Rate => (
+                format!("Too many queries. Try again in {retry_after} s."),
+                "rate_limited",
+            ),
+            LimitReason::Concurrency => (
+                format!(
+                    "At most {} queries may run at once. Wait for one to finish.",
+                    self.quota.max_concurrent
+                ),
+                "too_many_concurrent_queries",
+            ),
+        };
+        json_response(
+            Status::TooManyRequests,
+            json!({ "error": error, "code": code, "retry_after": retry_after }),
+        )
+    }
+}
+
+/// The client a request counts against: its API key when one was checked, its IP otherwise.
+async fn client_id(request: &Request<'_>, config: &RateLimitConfig) -> String {
+    if let Outcome::Success(ApiKey(policy)) = request.guard::<ApiKey>().await {
+        if policy.name.is_some() {
+            if let Some(key) = auth::presented_key(request) {
+                return format!("key:{}", auth::hash_key(key));
+            }
+        }
+    }
+    let remote = request.remote().map(|remote| remote.ip());
+    match client_ip(remote, request.real_ip(), config) {
+        Some(ip) => format!("ip:{ip}"),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+/// The caller's address: the peer's, or the `X-Real-IP` one when the peer is a trusted proxy.
+fn client_ip(
+    remote: Option<IpAddr>,
+    real_ip: Option<IpAddr>,
+    config: &RateLimitConfig,
+) -> Option<IpAddr> {
+    match remote {
+        Some(remote) if config.trusted_proxies.contains(&remote) => real_ip.or(Some(remote)),
+        remote => remote,
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Permit {
+    type Error = RateLimited;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(limiter) = request.rocket().state::<RateLimiter>() else {
+            return Outcome::Forward(Status::InternalServerError);
+        };
+        let client = client_id(request, &limiter.config).await;
+        match limiter.acquire(&client) {
+            Ok(permit) => {
+                request.local_cache(|| Some(permit.quota.clone()));
+                Outcome::Success(permit)
+            }
+            Err(limited) => {
+                request.local_cache(|| Some(limited.quota.clone()));
+                Outcome::Error((Status::TooManyRequests, limited))
+            }
+        }
+    }
+}
+
+/// Adds the quota headers to responses from routes that took a [`Permit`].
+pub struct RateLimitHeaders;
+
+#[rocket::async_trait]
+impl Fairing for RateLimitHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Attaching rate limit headers to query responses",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if let Some(quota) = request.local_cache(|| None::<Quota>) {
+            for header in quota.headers() {
+                response.set_header(header);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            per_minute: 60,
+            burst: 2,
+            max_concurrent: 0,
+            ..RateLimitConfig::default()
+        });
+        let start = Instant::now();
+        let first = limiter.acquire_at("a", start).unwrap();
+        assert_eq!(first.quota.remaining, 1);
+        limiter.acquire_at("a", start).unwrap();
+        let limited = limiter.acquire_at("a", start).err().unwrap();
+        assert_eq!(limited.reason, LimitReason::Rate);
+        assert_eq!(limited.quota.retry_after, Some(Duration::from_secs(1)));
+        // Other clients have their own bucket.
+        limiter.acquire_at("b", start).unwrap();
+
+        let later = start + Duration::from_millis(1500);
+        let permit = limiter.acquire_at("a", later).unwrap();
+        assert_eq!(permit.quota.remaining, 0);
+        assert!(limiter.acquire_at("a", later).is_err());
+    }
+
+    #[test]
+    fn test_concurrency_limit() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            per_minute: 0,
+            burst: 1,
+            max_concurrent: 2,
+            ..RateLimitConfig::default()
+        });
+        let now = Instant::now();
+        let first = limiter.acquire_at("a", now).unwrap();
+        let second = limiter.acquire_at("a", now).unwrap();
+        assert_eq!(second.quota.headers().len(), 2);
+        let limited = limiter.acquire_at("a", now).err().unwrap();
+        assert_eq!(limited.reason, LimitReason::Concurrency);
+        assert!(limited
+            .quota
+            .headers()
+            .iter()
+            .any(|h| h.name() == "Retry-After" && h.value() == "1"));
+
+        drop(first);
+        let third = limiter.acquire_at("a", now).unwrap();
+        drop((second, third));
+        assert_eq!(limiter.clients.lock().unwrap().map["a"].running, 0);
+    }
+
+    #[test]
+    fn test_max_clients() {
+        let mut limiter = RateLimiter::new(RateLimitConfig::default());
+        limiter.max_clients = 2;
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        limiter.acquire_at("a", at(0)).unwrap();
+        limiter.acquire_at("b", at(1)).unwrap();
+        // Seeing `a` again makes `b` the one to forget.
+        limiter.acquire_at("a", at(2)).unwrap();
+        limiter.acquire_at("c", at(3)).unwrap();
+        let clients = limiter.clients.lock().unwrap();
+        assert_eq!(clients.map.len(), 2);
+        assert!(clients.map.contains_key("a") && clients.map.contains_key("c"));
+        assert_eq!(clients.queue.len(), 2);
+    }
+
+    #[test]
+    fn test_max_clients_keeps_running_clients() {
+        let mut limiter = RateLimiter::new(RateLimitConfig::default());
+        limiter.max_clients = 2;
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let running = limiter.acquire_at("a", at(0)).unwrap();
+        limiter.acquire_at("b", at(1)).unwrap();
+        // `a` was seen longest ago, but its query still holds a slot.
+        limiter.acquire_at("c", at(2)).unwrap();
+        assert!(!limiter.clients.lock().unwrap().map.contains_key("b"));
+
+        // With every client busy, the map grows past the cap instead.
+        let also_running = limiter.acquire_at("c", at(3)).unwrap();
+        limiter.acquire_at("d", at(4)).unwrap();
+        assert_eq!(limiter.clients.lock().unwrap().map.len(), 3);
+
+        drop((running, also_running));
+        let clients = limiter.clients.lock().unwrap();
+        assert_eq!(clients.map["a"].running, 0);
+        assert_eq!(clients.map["c"].running, 0);
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let peer = "203.0.113.7".parse().unwrap();
+        let claimed = "198.51.100.2".parse().unwrap();
+        let config = RateLimitConfig {
+            trusted_proxies: vec![proxy],
+            ..RateLimitConfig::default()
+        };
+        assert_eq!(client_ip(Some(peer), Some(claimed), &config), Some(peer));
+        assert_eq!(
+            client_ip(Some(proxy), Some(claimed), &config),
+            Some(claimed)
+        );
+        assert_eq!(client_ip(Some(proxy), None, &config), Some(proxy));
+        let untrusting = RateLimitConfig::default();
+        assert_eq!(
+            client_ip(Some(proxy), Some(claimed), &untrusting),
+            Some(proxy)
+        );
+    }
+}
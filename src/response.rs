@@ -0,0 +1,169 @@
+use futures::stream::{BoxStream, StreamExt};
+use rocket::{
+    http::{ContentType, Header, Status},
+    response::{self, content::RawJson, status, stream::TextStream, Responder},
+    Request, Response,
+};
+
+/// What the query routes send back: a complete JSON document, rows streamed to the client as
+/// they are read, or a file to download.
+pub enum QueryResponse {
+    Json {
+        response: status::Custom<RawJson<String>>,
+        headers: Vec<Header<'static>>,
+    },
+    Stream((ContentType, TextStream<BoxStream<'static, String>>)),
+    Attachment {
+        body: Vec<u8>,
+        content_type: ContentType,
+        filename: String,
+        headers: Vec<Header<'static>>,
+    },
+}
+
+impl QueryResponse {
+    /// Newline-delimited JSON, one line per item. The body is sent with chunked transfer
+    /// encoding since its length is not known up front.
+    pub fn ndjson(lines: BoxStream<'static, String>) -> Self {
+        QueryResponse::Stream((
+            ContentType::new("application", "x-ndjson"),
+            TextStream(lines),
+        ))
+    }
+
+    /// A download named after the engine and the time of the request, e.g.
+    /// `sandworm-indexed-20250101T120000Z.csv`.
+    pub fn attachment(
+        body: Vec<u8>,
+        content_type: ContentType,
+        engine: &str,
+        extension: &str,
+    ) -> Self {
+        QueryResponse::Attachment {
+            body,
+            content_type,
+            filename: attachment_name(engine, extension),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn csv(body: String, engine: &str) -> Self {
+        Self::attachment(body.into_bytes(), ContentType::CSV, engine, "csv")
+    }
+
+    pub fn arrow(body: Vec<u8>, engine: &str) -> Self {
+        let content_type = ContentType::new("application", "vnd.apache.arrow.stream");
+        Self::attachment(body, content_type, engine, "arrows")
+    }
+
+    pub fn parquet(body: Vec<u8>, engine: &str) -> Self {
+        let content_type = ContentType::new("application", "vnd.apache.parquet");
+        Self::attachment(body, content_type, engine, "parquet")
+    }
+
+    /// Keeps `guard` alive until the response is done with it: for a stream, until the last line
+    /// has been sent.
+    pub fn holding<T: Send + 'static>(self, guard: T) -> Self {
+        match self {
+            QueryResponse::Stream((content_type, TextStream(lines))) => {
+                let lines = lines.map(move |line| {
+                    let _ = &guard;
+                    line
+                });
+                QueryResponse::Stream((content_type, TextStream(lines.boxed())))
+            }
+            response => response,
+        }
+    }
+
+    /// Adds a header to a JSON document or an attachment; streams are returned unchanged.
+    pub fn with_header(mut self, header: Header<'static>) -> Self {
+        match &mut self {
+            QueryResponse::Json { headers, .. } | QueryResponse::Attachment { headers, .. } => {
+                headers.push(header)
+            }
+            QueryResponse::Stream(_) => {}
+        }
+        self
+    }
+
+    /// The complete body of a successful JSON document or attachment; `None` for streams and
+    /// errors.
+    pub fn body(&self) -> Option<&[u8]> {
+        match self {
+            QueryResponse::Json { response, .. } if response.0 == Status::Ok => {
+                Some((response.1).0.as_bytes())
+            }
+            QueryResponse::Attachment { body, .. } => Some(body),
+            _ => None,
+        }
+    }
+
+    /// A copy of a response that has a [`body`](QueryResponse::body).
+    pub fn try_clone(&self) -> Option<Self> {
+        self.body()?;
+        match self {
+            QueryResponse::Json { response, headers } => Some(QueryResponse::Json {
+                response: status::Custom(response.0, RawJson((response.1).0.clone())),
+                headers: headers.clone(),
+            }),
+            QueryResponse::Attachment {
+                body,
+                content_type,
+                filename,
+                headers,
+            } => Some(QueryResponse::Attachment {
+                body: body.clone(),
+                content_type: content_type.clone(),
+                filename: filename.clone(),
+                headers: headers.clone(),
+            }),
+            QueryResponse::Stream(_) => None,
+        }
+    }
+}
+
+fn attachment_name(engine: &str, extension: &str) -> String {
+    let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ");
+    format!("sandworm-{}-{}.{}", engine.to_lowercase(), stamp, extension)
+}
+
+impl From<status::Custom<RawJson<String>>> for QueryResponse {
+    fn from(response: status::Custom<RawJson<String>>) -> Self {
+        QueryResponse::Json {
+            response,
+            headers: Vec::new(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'r> for QueryResponse {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'r> {
+        match self {
+            QueryResponse::Json { response, headers } => {
+                let mut response = Response::build_from(response.respond_to(request)?);
+                for header in headers {
+                    response.header(header);
+                }
+                response.ok()
+            }
+            QueryResponse::Stream(response) => response.respond_to(request),
+            QueryResponse::Attachment {
+                body,
+                content_type,
+                filename,
+                headers,
+            } => {
+                let mut response = Response::build_from(body.respond_to(request)?);
+                response.header(content_type).raw_header(
+                    "Content-Disposition",
+                    format!("attachment; filename=\"{filename}\""),
+                );
+                for header in headers {
+                    response.header(header);
+                }
+                response.ok()
+            }
+        }
+    }
+}
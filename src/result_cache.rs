@@ -0,0 +1,419 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::Cursor;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{Header, Method, Status},
+    Request, Response,
+};
+use serde::Deserialize;
+
+use crate::config::env_parse;
+use crate::response::QueryResponse;
+use crate::utils::sha256_hex;
+
+/// Whether a request may be answered from the result cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheMode {
+    #[default]
+    Use,
+    /// Always runs the query. The fresh result still replaces the cached one.
+    Bypass,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// How long an indexed result is served from the cache (`RESULT_CACHE_TTL_SECS`). 0 turns
+    /// caching of indexed results off.
+    pub ttl: Duration,
+    /// How long an RPC result that depends on the chain head is cached, for chains without
+    /// their own `latest_ttl_secs` (`RPC_CACHE_LATEST_TTL_SECS`). RPC results for final blocks
+    /// are kept until evicted for space.
+    pub latest_ttl: Duration,
+    /// Total size of cached bodies; the oldest entries are dropped to stay under it
+    /// (`RESULT_CACHE_MAX_BYTES`).
+    pub max_bytes: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(30),
+            latest_ttl: Duration::from_secs(2),
+            max_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            ttl: Duration::from_secs(env_parse("RESULT_CACHE_TTL_SECS", defaults.ttl.as_secs())),
+            latest_ttl: Duration::from_secs(env_parse(
+                "RPC_CACHE_LATEST_TTL_SECS",
+                defaults.latest_ttl.as_secs(),
+            )),
+            max_bytes: env_parse("RESULT_CACHE_MAX_BYTES", defaults.max_bytes),
+        }
+    }
+}
+
+struct Entry {
+    response: QueryResponse,
+    etag: String,
+    size: usize,
+    /// Tells this entry's place in [`Entries::order`] from those of earlier entries for the key.
+    seq: u64,
+    /// `None` for results that never go stale.
+    expires: Option<Instant>,
+}
+
+impl Entry {
+    fn expired(&self, now: Instant) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<String, Entry>,
+    bytes: usize,
+    /// Keys in the order they were stored, oldest first. Entries that were replaced or removed
+    /// since are left behind and skipped, so eviction is O(1) amortized.
+    order: VecDeque<(u64, String)>,
+    next_seq: u64,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.map.remove(key) {
+            self.bytes -= entry.size;
+        }
+    }
+
+    fn is_current(&self, seq: u64, key: &str) -> bool {
+        self.map.get(key).is_some_and(|entry| entry.seq == seq)
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        self.next_seq += 1;
+        self.next_seq
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) {
+        self.bytes += entry.size;
+        self.order.push_back((entry.seq, key.clone()));
+        self.map.insert(key, entry);
+        // Left-behind keys would otherwise pile up while nothing needs evicting.
+        if self.order.len() > 2 * self.map.len() {
+            let Entries { map, order, .. } = self;
+            order.retain(|(seq, key)| map.get(key).is_some_and(|entry| entry.seq == *seq));
+        }
+    }
+
+    /// Drops the entry stored longest ago, if any.
+    fn evict_oldest(&mut self) -> bool {
+        while let Some((seq, key)) = self.order.pop_front() {
+            if self.is_current(seq, &key) {
+                self.remove(&key);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Complete query responses, shared by every client and kept in Rocket's managed state.
+/// Responses served from it or stored in it carry an `ETag`, a `Cache-Control` max-age for the
+/// rest of their lifetime, and `X-Cache: HIT` or `MISS`.
+pub struct ResultCache {
+    config: CacheConfig,
+    entries: Mutex<Entries>,
+}
+
+impl ResultCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// A cache key for a query, from everything that shapes its response.
+    pub fn key(parts: &[&str]) -> String {
+        sha256_hex(parts.join("\0").as_bytes())
+    }
+
+    pub fn get(&self, key: &str) -> Option<QueryResponse> {
+        self.get_at(key, Instant::now())
+    }
+
+    /// Caches `response` under `key` for the configured TTL when it is complete and
+    /// successful, and returns it with the cache headers. Other responses come back unchanged.
+    pub fn store(&self, key: String, response: QueryResponse) -> QueryResponse {
+        self.store_for(key, response, Some(self.config.ttl))
+    }
+
+    /// Like [`ResultCache::store`], for `ttl` instead. `None` keeps the response until it is
+    /// evicted for space.
+    pub fn store_for(
+        &self,
+        key: String,
+        response: QueryResponse,
+        ttl: Option<Duration>,
+    ) -> QueryResponse {
+        self.store_at(key, response, ttl, Instant::now())
+    }
+
+    fn get_at(&self, key: &str, now: Instant) -> Option<QueryResponse> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = entries.map.get(key)?;
+        if entry.expired(now) {
+            entries.remove(key);
+            return None;
+        }
+        let response = entry.response.try_clone()?;
+        let fresh_for = entry.expires.map(|expires| expires - now);
+        Some(tag(response, &entry.etag, fresh_for, "HIT"))
+    }
+
+    fn store_at(
+        &self,
+        key: String,
+        response: QueryResponse,
+        ttl: Option<Duration>,
+        now: Instant,
+    ) -> QueryResponse {
+        if ttl.is_some_and(|ttl| ttl.is_zero()) {
+            return response;
+        }
+        let Some(body) = response.body() else {
+            return response;
+        };
+        let etag = format!("\"{}\"", &sha256_hex(body)[..32]);
+        let size = body.len() + key.len();
+        let Some(copy) = response.try_clone() else {
+            return response;
+        };
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.remove(&key);
+        if size <= self.config.max_bytes {
+            while entries.bytes + size > self.config.max_bytes && entries.evict_oldest() {}
+            let seq = entries.next_seq();
+            entries.insert(
+                key,
+                Entry {
+                    response: copy,
+                    etag: etag.clone(),
+                    size,
+                    seq,
+                    expires: ttl.map(|ttl| now + ttl),
+                },
+            );
+        }
+        tag(response, &etag, ttl, "MISS")
+    }
+}
+
+fn tag(
+    response: QueryResponse,
+    etag: &str,
+    fresh_for: Option<Duration>,
+    status: &str,
+) -> QueryResponse {
+    let cache_control = match fresh_for {
+        Some(fresh_for) => format!("private, max-age={}", fresh_for.as_secs()),
+        None => "private, max-age=31536000, immutable".to_string(),
+    };
+    response
+        .with_header(Header::new("ETag", etag.to_string()))
+        .with_header(Header::new("Cache-Control", cache_control))
+        .with_header(Header::new("X-Cache", status.to_string()))
+}
+
+/// Whether an `If-None-Match` header names `etag`. Weak validators compare equal to strong ones,
+/// as they should for `GET`.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .map(|candidate| candidate.trim().trim_start_matches("W/"))
+            .any(|candidate| candidate == etag)
+}
+
+/// Answers `304 Not Modified` with no body when a `GET` or `HEAD` request's `If-None-Match`
+/// names the `ETag` of the response it would get. Other methods, such as `POST /v1/query`, get
+/// the full response: a 304 is only meaningful for reads, and many clients mishandle it on a
+/// `POST`.
+pub struct ConditionalGet;
+
+#[rocket::async_trait]
+impl Fairing for ConditionalGet {
+    fn info(&self) -> Info {
+        Info {
+            name: "Answering conditional requests for cached results",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.status() != Status::Ok
+            || !matches!(request.method(), Method::Get | Method::Head)
+        {
+            return;
+        }
+        let (Some(if_none_match), Some(etag)) = (
+            request.headers().get_one("If-None-Match"),
+            response.headers().get_one("ETag"),
+        ) else {
+            return;
+        };
+        if etag_matches(if_none_match, etag) {
+            response.set_status(Status::NotModified);
+            response.set_sized_body(0, Cursor::new(""));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::asynchronous::Client;
+    use rocket::response::{content::RawJson, status};
+
+    fn json(body: &str) -> QueryResponse {
+        status::Custom(Status::Ok, RawJson(body.to_string())).into()
+    }
+
+    fn header<'a>(response: &'a QueryResponse, name: &str) -> Option<&'a str> {
+        let (QueryResponse::Json { headers, .. } | QueryResponse::Attachment { headers, .. }) =
+            response
+        else {
+            return None;
+        };
+        headers
+            .iter()
+            .find(|header| header.name() == name)
+            .map(|header| header.value())
+    }
+
+    #[test]
+    fn test_store_and_expire() {
+        let cache = ResultCache::new(CacheConfig {
+            ttl: Duration::from_secs(30),
+            max_bytes: 1024,
+            ..CacheConfig::default()
+        });
+        let now = Instant::now();
+        let key = ResultCache::key(&["sql", "SELECT 1"]);
+        let stored = cache.store_at(key.clone(), json("[1]"), Some(Duration::from_secs(30)), now);
+        assert_eq!(header(&stored, "X-Cache"), Some("MISS"));
+        assert_eq!(
+            header(&stored, "Cache-Control"),
+            Some("private, max-age=30")
+        );
+
+        let hit = cache
+            .get_at(&key, now + Duration::from_secs(10))
+            .expect("cached");
+        assert_eq!(hit.body(), Some(&b"[1]"[..]));
+        assert_eq!(header(&hit, "X-Cache"), Some("HIT"));
+        assert_eq!(header(&hit, "ETag"), header(&stored, "ETag"));
+        assert_eq!(header(&hit, "Cache-Control"), Some("private, max-age=20"));
+
+        assert!(cache.get_at(&key, now + Duration::from_secs(30)).is_none());
+        assert_eq!(cache.entries.lock().unwrap().bytes, 0);
+
+        let error = status::Custom(Status::BadRequest, RawJson("{}".to_string())).into();
+        cache.store_at(key.clone(), error, None, now);
+        assert!(cache.get_at(&key, now).is_none());
+
+        let pinned = cache.store_at(key.clone(), json("[2]"), None, now);
+        assert_eq!(
+            header(&pinned, "Cache-Control"),
+            Some("private, max-age=31536000, immutable")
+        );
+        let much_later = now + Duration::from_secs(86_400);
+        assert!(cache.get_at(&key, much_later).is_some());
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let cache = ResultCache::new(CacheConfig {
+            ttl: Duration::from_secs(30),
+            max_bytes: 200,
+            ..CacheConfig::default()
+        });
+        let now = Instant::now();
+        let body = "x".repeat(80);
+        for (i, key) in ["a", "b", "c"].into_iter().enumerate() {
+            let at = now + Duration::from_secs(i as u64);
+            cache.store_at(key.to_string(), json(&body), None, at);
+        }
+        // The oldest entry made room for the third.
+        assert!(cache.get_at("a", now).is_none());
+        assert!(cache.get_at("b", now).is_some());
+        assert!(cache.get_at("c", now).is_some());
+
+        cache.store_at("big".to_string(), json(&"x".repeat(300)), None, now);
+        assert!(cache.get_at("big", now).is_none());
+        assert!(cache.get_at("c", now).is_some());
+
+        // Storing a key again leaves its old place behind, which is skipped and cleared out.
+        for _ in 0..10 {
+            cache.store_at("c".to_string(), json(&body), None, now);
+        }
+        cache.store_at("d".to_string(), json(&body), None, now);
+        assert!(cache.get_at("b", now).is_none());
+        assert!(cache.get_at("c", now).is_some());
+        let entries = cache.entries.lock().unwrap();
+        assert_eq!(entries.bytes, 2 * 81);
+        assert!(entries.order.len() <= 2 * entries.map.len());
+    }
+
+    fn tagged() -> QueryResponse {
+        json("[1]").with_header(Header::new("ETag", "\"abc\""))
+    }
+
+    #[rocket::get("/")]
+    fn tagged_get() -> QueryResponse {
+        tagged()
+    }
+
+    #[rocket::post("/")]
+    fn tagged_post() -> QueryResponse {
+        tagged()
+    }
+
+    #[rocket::async_test]
+    async fn test_conditional_get() {
+        let rocket = rocket::build()
+            .attach(ConditionalGet)
+            .mount("/", rocket::routes![tagged_get, tagged_post]);
+        let client = Client::tracked(rocket).await.unwrap();
+        let if_none_match = || Header::new("If-None-Match", "\"abc\"");
+
+        let get = client.get("/").header(if_none_match()).dispatch().await;
+        assert_eq!(get.status(), Status::NotModified);
+        assert_eq!(get.into_string().await.as_deref(), Some(""));
+        let head = client.head("/").header(if_none_match()).dispatch().await;
+        assert_eq!(head.status(), Status::NotModified);
+
+        let post = client.post("/").header(if_none_match()).dispatch().await;
+        assert_eq!(post.status(), Status::Ok);
+        assert_eq!(post.into_string().await.as_deref(), Some("[1]"));
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        assert!(etag_matches("\"abc\"", "\"abc\""));
+        assert!(etag_matches("W/\"abc\", \"def\"", "\"abc\""));
+        assert!(etag_matches("*", "\"abc\""));
+        assert!(!etag_matches("\"abd\"", "\"abc\""));
+    }
+}
@@ -0,0 +1,736 @@
+use std::fmt;
+use std::ops::ControlFlow;
+
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlparser::ast::{Expr, ObjectName, Query, SetExpr, Statement, TableFactor, Visit, Visitor};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::{Parser, ParserError};
+use sqlparser::tokenizer::{Location, Token, TokenWithLocation, Tokenizer, Whitespace};
+
+use crate::config::env_list;
+
+/// Functions that read server state, touch the filesystem, sleep, or otherwise have side
+/// effects even when called from a plain SELECT. A trailing `*` matches by prefix.
+const DEFAULT_DENIED_FUNCTIONS: &[&str] = &[
+    // Sleeping, locking and backend control
+    "pg_sleep*",
+    "pg_cancel_backend",
+    "pg_terminate_backend",
+    "pg_reload_conf",
+    "pg_rotate_logfile",
+    "pg_advisory_*",
+    "pg_try_advisory_*",
+    "pg_backend_pid",
+    "pg_postmaster_start_time",
+    // Settings and sequences
+    "set_config",
+    "current_setting",
+    "nextval",
+    "setval",
+    // Transactions, WAL and replication
+    "pg_current_xact_id",
+    "txid_current",
+    "pg_is_in_recovery",
+    "pg_last_xact_replay_timestamp",
+    "pg_switch_wal",
+    "pg_current_wal_lsn",
+    "pg_wal_lsn_diff",
+    "pg_create_*",
+    "pg_drop_*",
+    "pg_replication_*",
+    "pg_start_backup",
+    "pg_stop_backup",
+    "pg_promote",
+    "pg_logical_*",
+    // Filesystem and large objects
+    "pg_read_file",
+    "pg_read_binary_file",
+    "pg_stat_file",
+    "pg_ls_*",
+    "pg_file_*",
+    "pg_log_*",
+    "lo_*",
+    "dblink*",
+    // Functions that execute a query given as a string
+    "query_to_xml*",
+    "cursor_to_xml*",
+    // Statistics and size introspection
+    "pg_stat_*",
+    "pg_size_pretty",
+    "pg_table_size",
+    "pg_database_size",
+    "pg_indexes_size",
+    "pg_total_relation_size",
+    "pg_column_size",
+    "pg_relation_size",
+    // Network and identity
+    "inet_client_addr",
+    "inet_client_port",
+    "inet_server_addr",
+    "inet_server_port",
+    "current_user",
+    "session_user",
+    "system_user",
+];
+
+/// System catalogs and views, and the server's own tables, that must not be read by API users.
+const DEFAULT_DENIED_RELATIONS: &[&str] = &[
+    // Key hashes and policies, see `auth::ensure_table`
+    "api_keys",
+    "pg_catalog",
+    "information_schema",
+    "pg_stat_*",
+    "pg_statio_*",
+    "pg_replication_*",
+    "pg_settings",
+    "pg_file_settings",
+    "pg_hba_file_rules",
+    "pg_ident_file_mappings",
+    "pg_tablespace",
+    "pg_database",
+    "pg_user",
+    "pg_roles",
+    "pg_shadow",
+    "pg_authid",
+    "pg_auth_members",
+    "pg_group",
+];
+
+/// Deny-lists applied by [`validate_read_only`].
+#[derive(Debug, Clone)]
+pub struct GuardConfig {
+    pub denied_functions: Vec<String>,
+    pub denied_relations: Vec<String>,
+}
+
+impl Default for GuardConfig {
+    fn default() -> Self {
+        Self {
+            denied_functions: DEFAULT_DENIED_FUNCTIONS
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            denied_relations: DEFAULT_DENIED_RELATIONS
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl GuardConfig {
+    /// Default deny-lists extended with the comma-separated `SQL_DENIED_FUNCTIONS` and
+    /// `SQL_DENIED_RELATIONS` environment variables.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        config
+            .denied_functions
+            .extend(env_list("SQL_DENIED_FUNCTIONS"));
+        config
+            .denied_relations
+            .extend(env_list("SQL_DENIED_RELATIONS"));
+        config
+    }
+
+    fn is_denied_function(&self, name: &ObjectName) -> bool {
+        name_matches(name, &self.denied_functions)
+    }
+
+    fn is_denied_relation(&self, name: &ObjectName) -> bool {
+        name_matches(name, &self.denied_relations)
+    }
+}
+
+fn name_matches(name: &ObjectName, patterns: &[String]) -> bool {
+    name.0.iter().any(|ident| {
+        let ident = ident.value.to_lowercase();
+        patterns.iter().any(|pattern| {
+            let pattern = pattern.to_lowercase();
+            match pattern.strip_suffix('*') {
+                Some(prefix) => ident.starts_with(prefix),
+                None => ident == pattern,
+            }
+        })
+    })
+}
+
+/// The rule a rejected query broke.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GuardRule {
+    Parse(String),
+    Empty,
+    MultipleStatements,
+    NotAQuery(String),
+    DeniedFunction(ObjectName),
+    DeniedRelation(ObjectName),
+    SelectInto,
+    LockingClause,
+}
+
+impl GuardRule {
+    /// Machine-readable rule name sent to clients as `code`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            GuardRule::Parse(_) => "syntax_error",
+            GuardRule::Empty => "empty_query",
+            GuardRule::MultipleStatements => "multiple_statements",
+            GuardRule::NotAQuery(_) => "statement_not_allowed",
+            GuardRule::DeniedFunction(_) => "function_not_allowed",
+            GuardRule::DeniedRelation(_) => "relation_not_allowed",
+            GuardRule::SelectInto => "select_into_not_allowed",
+            GuardRule::LockingClause => "locking_clause_not_allowed",
+        }
+    }
+
+    /// The offending token or name, if the rule is about a specific one.
+    pub fn token(&self) -> Option<String> {
+        match self {
+            GuardRule::Parse(_) | GuardRule::Empty => None,
+            GuardRule::MultipleStatements => Some(";".to_string()),
+            GuardRule::NotAQuery(kind) => Some(kind.clone()),
+            GuardRule::DeniedFunction(name) | GuardRule::DeniedRelation(name) => {
+                Some(name.to_string())
+            }
+            GuardRule::SelectInto => Some("INTO".to_string()),
+            GuardRule::LockingClause => Some("FOR".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for GuardRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GuardRule::Parse(e) => write!(f, "Could not parse query: {e}"),
+            GuardRule::Empty => write!(f, "Query is empty"),
+            GuardRule::MultipleStatements => {
+                write!(
+                    f,
+                    "Only a single statement is allowed; remove the ';' separator"
+                )
+            }
+            GuardRule::NotAQuery(kind) => write!(
+                f,
+                "Only SELECT queries are allowed, found {kind}. CREATE, DROP, INSERT, UPDATE, \
+                 DELETE, and other write ops are blocked."
+            ),
+            GuardRule::DeniedFunction(name) => write!(f, "Function {name} is not allowed"),
+            GuardRule::DeniedRelation(name) => write!(f, "Access to {name} is not allowed"),
+            GuardRule::SelectInto => write!(f, "SELECT ... INTO is not allowed"),
+            GuardRule::LockingClause => {
+                write!(f, "Locking clauses (FOR UPDATE/SHARE) are not allowed")
+            }
+        }
+    }
+}
+
+/// Where the offending text sits in the submitted SQL. Lines and columns start at 1 and
+/// count characters; `end_column` is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Span {
+    pub line: u64,
+    pub column: u64,
+    pub end_line: u64,
+    pub end_column: u64,
+}
+
+impl Span {
+    fn point(location: Location) -> Self {
+        Span {
+            line: location.line,
+            column: location.column,
+            end_line: location.line,
+            end_column: location.column,
+        }
+    }
+
+    fn covering(first: &TokenWithLocation, last: &TokenWithLocation) -> Self {
+        Span {
+            line: first.location.line,
+            column: first.location.column,
+            end_line: last.location.line,
+            end_column: last.location.column + last.token.to_string().chars().count() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuardError {
+    pub rule: GuardRule,
+    pub span: Option<Span>,
+}
+
+impl GuardError {
+    /// The error body returned to clients, e.g.
+    /// `{"error": "...", "code": "function_not_allowed", "token": "pg_sleep", "position": {...}}`.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "error": self.rule.to_string(),
+            "code": self.rule.code(),
+            "token": self.rule.token(),
+            "position": self.span,
+        })
+    }
+}
+
+impl fmt::Display for GuardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(
+                f,
+                "{} (line {}, column {})",
+                self.rule, span.line, span.column
+            ),
+            None => write!(f, "{}", self.rule),
+        }
+    }
+}
+
+impl std::error::Error for GuardError {}
+
+/// Parses `sql` and accepts it only if it is a single read-only query.
+///
+/// Unlike a keyword blacklist this only looks at what the statement does, so column names
+/// such as `size` or string literals containing `;` are fine. RPC queries are not SQL and
+/// are not checked here; the EQL and SuiQL grammars have no write operations.
+pub fn validate_read_only(sql: &str, config: &GuardConfig) -> Result<Box<Query>, GuardError> {
+    let dialect = PostgreSqlDialect {};
+
+    let tokens = Tokenizer::new(&dialect, sql)
+        .tokenize_with_location()
+        .map_err(|e| GuardError {
+            rule: GuardRule::Parse(e.message),
+            span: Some(Span::point(e.location)),
+        })?;
+    let reject = |rule: GuardRule| {
+        let span = locate(&tokens, &rule);
+        GuardError { rule, span }
+    };
+
+    let mut statements = Parser::new(&dialect)
+        .with_tokens_with_locations(tokens.clone())
+        .parse_statements()
+        .map_err(|e| parse_error(&tokens, e))?;
+
+    let statement = match statements.len() {
+        0 => return Err(reject(GuardRule::Empty)),
+        1 => statements.remove(0),
+        _ => return Err(reject(GuardRule::MultipleStatements)),
+    };
+    // One `;` may end the query, followed by nothing but blanks. A second one or a comment
+    // after it is refused along with stacked statements.
+    if let Some(end) = tokens.iter().position(|t| t.token == Token::SemiColon) {
+        let blank = |t: &TokenWithLocation| {
+            matches!(
+                t.token,
+                Token::Whitespace(Whitespace::Space | Whitespace::Newline | Whitespace::Tab)
+            )
+        };
+        if !tokens[end + 1..].iter().all(blank) {
+            return Err(reject(GuardRule::MultipleStatements));
+        }
+    }
+
+    if let ControlFlow::Break(rule) = statement.visit(&mut ReadOnlyVisitor { config }) {
+        return Err(reject(rule));
+    }
+
+    match statement {
+        Statement::Query(query) => Ok(query),
+        other => Err(reject(statement_kind(&other))),
+    }
+}
+
+/// Splits the `at Line: 1, Column 8` suffix sqlparser appends to its messages into a span.
+fn parse_error(tokens: &[TokenWithLocation], err: ParserError) -> GuardError {
+    let message = match err {
+        ParserError::TokenizerError(m) | ParserError::ParserError(m) => m,
+        ParserError::RecursionLimitExceeded => "query is nested too deeply".to_string(),
+    };
+    let re = Regex::new(r"^(?s)(.*) at Line: (\d+), Column (\d+)$").unwrap();
+    let Some(caps) = re.captures(&message) else {
+        return GuardError {
+            rule: GuardRule::Parse(message),
+            span: None,
+        };
+    };
+    let location = Location {
+        line: caps[2].parse().unwrap_or(0),
+        column: caps[3].parse().unwrap_or(0),
+    };
+    let span = tokens
+        .iter()
+        .find(|t| t.location == location)
+        .map(|t| Span::covering(t, t))
+        .unwrap_or_else(|| Span::point(location));
+    GuardError {
+        rule: GuardRule::Parse(caps[1].to_string()),
+        span: Some(span),
+    }
+}
+
+/// Finds the first occurrence of the text a rule complains about.
+fn locate(tokens: &[TokenWithLocation], rule: &GuardRule) -> Option<Span> {
+    let tokens: Vec<&TokenWithLocation> = tokens
+        .iter()
+        .filter(|t| !matches!(t.token, Token::Whitespace(_)))
+        .collect();
+    let is_word = |t: &TokenWithLocation, value: &str| match &t.token {
+        Token::Word(w) => w.value.eq_ignore_ascii_case(value),
+        _ => false,
+    };
+    let single = |t: &&TokenWithLocation| Span::covering(t, t);
+
+    match rule {
+        GuardRule::Parse(_) | GuardRule::Empty => None,
+        GuardRule::MultipleStatements => tokens
+            .iter()
+            .find(|t| t.token == Token::SemiColon)
+            .map(single),
+        GuardRule::NotAQuery(kind) => {
+            let keyword = kind.split_whitespace().next()?;
+            tokens.iter().find(|t| is_word(t, keyword)).map(single)
+        }
+        GuardRule::SelectInto => tokens.iter().find(|t| is_word(t, "INTO")).map(single),
+        GuardRule::LockingClause => tokens
+            .windows(2)
+            .find(|w| {
+                is_word(w[0], "FOR")
+                    && ["UPDATE", "SHARE", "NO", "KEY"]
+                        .iter()
+                        .any(|k| is_word(w[1], k))
+            })
+            .map(|w| Span::covering(w[0], w[1])),
+        GuardRule::DeniedFunction(name) | GuardRule::DeniedRelation(name) => {
+            // A qualified name is `ident . ident . ident` in the token stream.
+            let len = name.0.len() * 2 - 1;
+            let matches_at = |start: usize| {
+                name.0.iter().enumerate().all(|(i, ident)| {
+                    tokens
+                        .get(start + i * 2)
+                        .is_some_and(|t| is_word(t, &ident.value))
+                        && (i == 0 || tokens[start + i * 2 - 1].token == Token::Period)
+                })
+            };
+            let mut found = (0..tokens.len()).filter(|&start| matches_at(start));
+            let first = found.next()?;
+            // Prefer a call site over a bare column of the same name.
+            let start = if matches!(rule, GuardRule::DeniedFunction(_)) {
+                std::iter::once(first)
+                    .chain(found)
+                    .find(|&start| {
+                        tokens
+                            .get(start + len)
+                            .is_some_and(|t| t.token == Token::LParen)
+                    })
+                    .unwrap_or(first)
+            } else {
+                first
+            };
+            Some(Span::covering(tokens[start], tokens[start + len - 1]))
+        }
+    }
+}
+
+/// Walks every statement, query, relation and expression in the tree, so writes nested in
+/// CTEs, derived tables or subqueries are caught as well.
+struct ReadOnlyVisitor<'a> {
+    config: &'a GuardConfig,
+}
+
+impl Visitor for ReadOnlyVisitor<'_> {
+    type Break = GuardRule;
+
+    fn pre_visit_statement(&mut self, statement: &Statement) -> ControlFlow<GuardRule> {
+        match statement {
+            Statement::Query(_) => ControlFlow::Continue(()),
+            other => ControlFlow::Break(statement_kind(other)),
+        }
+    }
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<GuardRule> {
+        if !query.locks.is_empty() {
+            return ControlFlow::Break(GuardRule::LockingClause);
+        }
+        check_select_into(&query.body)
+    }
+
+    fn pre_visit_relation(&mut self, relation: &ObjectName) -> ControlFlow<GuardRule> {
+        if self.config.is_denied_relation(relation) {
+            return ControlFlow::Break(GuardRule::DeniedRelation(relation.clone()));
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, table: &TableFactor) -> ControlFlow<GuardRule> {
+        // Set-returning functions such as `pg_ls_dir('.')` are called in FROM as well.
+        let name = match table {
+            TableFactor::Table {
+                name,
+                args: Some(_),
+                ..
+            }
+            | TableFactor::Function { name, .. } => name,
+            _ => return ControlFlow::Continue(()),
+        };
+        if self.config.is_denied_function(name) {
+            return ControlFlow::Break(GuardRule::DeniedRelation(name.clone()));
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<GuardRule> {
+        match expr {
+            Expr::Function(func) if self.config.is_denied_function(&func.name) => {
+                ControlFlow::Break(GuardRule::DeniedFunction(func.name.clone()))
+            }
+            _ => ControlFlow::Continue(()),
+        }
+    }
+}
+
+fn check_select_into(body: &SetExpr) -> ControlFlow<GuardRule> {
+    match body {
+        SetExpr::Select(select) if select.into.is_some() => {
+            ControlFlow::Break(GuardRule::SelectInto)
+        }
+        SetExpr::SetOperation { left, right, .. } => {
+            check_select_into(left)?;
+            check_select_into(right)
+        }
+        _ => ControlFlow::Continue(()),
+    }
+}
+
+/// Names the kind of a rejected statement by its leading keyword(s), e.g. `INSERT`.
+fn statement_kind(statement: &Statement) -> GuardRule {
+    let text = statement.to_string();
+    let kind: Vec<&str> = text
+        .split_whitespace()
+        .take_while(|word| word.chars().all(|c| c.is_ascii_alphabetic()))
+        .take(2)
+        .collect();
+    GuardRule::NotAQuery(kind.join(" ").to_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(sql: &str) -> Result<Box<Query>, GuardRule> {
+        validate_read_only(sql, &GuardConfig::default()).map_err(|e| e.rule)
+    }
+
+    #[test]
+    fn test_query_only_sql() {
+        assert!(check("SELECT * FROM users WHERE id = 1").is_ok());
+    }
+
+    #[test]
+    fn test_insert_sql_is_not_query_only() {
+        assert!(check("INSERT INTO users (name) VALUES ('Alice')").is_err());
+    }
+
+    #[test]
+    fn test_update_sql_is_not_query_only() {
+        assert!(check("UPDATE users SET name = 'Bob' WHERE id = 1").is_err());
+    }
+
+    #[test]
+    fn test_dangerous_function_call_is_not_query_only() {
+        assert!(matches!(
+            check("SELECT pg_sleep(10)"),
+            Err(GuardRule::DeniedFunction(name)) if name.to_string() == "pg_sleep"
+        ));
+    }
+
+    #[test]
+    fn test_safe_uppercase_select_query() {
+        assert!(check("SELECT name FROM USERS").is_ok());
+    }
+
+    #[test]
+    fn test_sql_injection_pattern() {
+        assert!(check("' OR '1'='1").is_err());
+    }
+
+    #[test]
+    fn test_union_select_attack() {
+        assert!(check("UNION SELECT password FROM users").is_err());
+    }
+
+    #[test]
+    fn test_with_comment_injection() {
+        assert!(check("SELECT * FROM users; -- drop table users;").is_err());
+    }
+
+    #[test]
+    fn test_comments_and_comment_markers_in_literals() {
+        assert!(check("SELECT * FROM t WHERE url = 'https://x' AND note = 'a--b'").is_ok());
+        assert!(check("/* top */ SELECT 1 -- why\nFROM t WHERE note = '/*'").is_ok());
+        assert_eq!(
+            check("SELECT 1 -- hidden\n; DROP TABLE users").unwrap_err(),
+            GuardRule::MultipleStatements
+        );
+    }
+
+    #[test]
+    fn test_stacked_statements_are_rejected() {
+        assert_eq!(
+            check("SELECT 1; DROP TABLE users").unwrap_err(),
+            GuardRule::MultipleStatements
+        );
+    }
+
+    #[test]
+    fn test_terminal_semicolon_is_allowed() {
+        assert!(check("SELECT * FROM eth_blocks;").is_ok());
+        assert!(check("SELECT * FROM eth_blocks ;\n").is_ok());
+        for sql in ["SELECT 1;;", "SELECT 1; SELECT 2;", "SELECT 1; /* x */"] {
+            assert_eq!(check(sql).unwrap_err(), GuardRule::MultipleStatements);
+        }
+        assert_eq!(check(";").unwrap_err(), GuardRule::Empty);
+    }
+
+    #[test]
+    fn test_former_blacklist_words_are_allowed() {
+        assert!(check("SELECT size, timezone, comment, \"user\" FROM eth_blocks").is_ok());
+        assert!(check("SELECT * FROM eth_logs WHERE data = 'a;b#c'").is_ok());
+        assert!(check("SELECT hash FROM eth_blocks UNION SELECT hash FROM base_blocks").is_ok());
+    }
+
+    #[test]
+    fn test_nested_denied_function_is_rejected() {
+        let sql = "SELECT * FROM (SELECT 1 AS x WHERE EXISTS (SELECT PG_CATALOG.PG_SLEEP(1))) t";
+        assert!(matches!(check(sql), Err(GuardRule::DeniedFunction(_))));
+    }
+
+    #[test]
+    fn test_system_catalogs_are_rejected() {
+        assert!(matches!(
+            check("SELECT * FROM pg_catalog.pg_settings"),
+            Err(GuardRule::DeniedRelation(_))
+        ));
+        assert!(matches!(
+            check("SELECT * FROM pg_ls_dir('.')"),
+            Err(GuardRule::DeniedRelation(_))
+        ));
+        // Function patterns such as `lo_*` only apply to functions called in FROM.
+        assert!(check("SELECT * FROM lo_trades JOIN pg_ledger ON true").is_ok());
+        assert!(matches!(
+            check("SELECT * FROM lo_export(1, '/tmp/x')"),
+            Err(GuardRule::DeniedRelation(_))
+        ));
+    }
+
+    #[test]
+    fn test_api_keys_are_rejected() {
+        for sql in [
+            "SELECT * FROM api_keys",
+            "SELECT key_hash FROM public.api_keys",
+            "SELECT * FROM eth_blocks WHERE EXISTS (SELECT 1 FROM \"API_KEYS\")",
+        ] {
+            assert!(
+                matches!(check(sql), Err(GuardRule::DeniedRelation(_))),
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_disguised_as_query_is_rejected() {
+        assert_eq!(
+            check("SELECT * INTO copy FROM users").unwrap_err(),
+            GuardRule::SelectInto
+        );
+        assert_eq!(
+            check("SELECT * FROM users FOR UPDATE").unwrap_err(),
+            GuardRule::LockingClause
+        );
+        assert!(matches!(
+            check("DELETE FROM users"),
+            Err(GuardRule::NotAQuery(kind)) if kind == "DELETE FROM"
+        ));
+    }
+
+    #[test]
+    fn test_bind_placeholders_are_accepted() {
+        assert!(
+            check("SELECT * FROM eth_logs WHERE address = $address AND block_number > :block")
+                .is_ok()
+        );
+        assert!(check("SELECT * FROM eth_logs WHERE block_number BETWEEN $1 AND $2").is_ok());
+    }
+
+    #[test]
+    fn test_configured_functions_are_rejected() {
+        let mut config = GuardConfig::default();
+        config.denied_functions.push("random".to_string());
+        assert!(validate_read_only("SELECT random()", &config).is_err());
+        assert!(check("SELECT random()").is_ok());
+    }
+
+    #[test]
+    fn test_error_points_at_denied_function() {
+        let sql = "SELECT number,\n       PG_CATALOG.pg_sleep(1)\nFROM eth_blocks";
+        let err = validate_read_only(sql, &GuardConfig::default()).unwrap_err();
+        assert_eq!(err.rule.code(), "function_not_allowed");
+        assert_eq!(
+            err.span,
+            Some(Span {
+                line: 2,
+                column: 8,
+                end_line: 2,
+                end_column: 27
+            })
+        );
+        let body = err.to_json();
+        assert_eq!(body["code"], "function_not_allowed");
+        assert_eq!(body["token"], "PG_CATALOG.pg_sleep");
+        assert_eq!(body["position"]["column"], 8);
+    }
+
+    #[test]
+    fn test_error_prefers_call_site_over_column() {
+        let sql = "SELECT pg_sleep FROM t WHERE pg_sleep(1) IS NULL";
+        let err = validate_read_only(sql, &GuardConfig::default()).unwrap_err();
+        assert_eq!(err.span.map(|s| s.column), Some(30));
+    }
+
+    #[test]
+    fn test_error_points_at_statement_and_separator() {
+        let err = validate_read_only("  DELETE FROM users", &GuardConfig::default()).unwrap_err();
+        assert_eq!(err.rule.code(), "statement_not_allowed");
+        assert_eq!(
+            err.span,
+            Some(Span {
+                line: 1,
+                column: 3,
+                end_line: 1,
+                end_column: 9
+            })
+        );
+
+        let err = validate_read_only("SELECT 1;\nSELECT 2", &GuardConfig::default()).unwrap_err();
+        assert_eq!(err.rule.code(), "multiple_statements");
+        assert_eq!(
+            err.span,
+            Some(Span {
+                line: 1,
+                column: 9,
+                end_line: 1,
+                end_column: 10
+            })
+        );
+    }
+
+    #[test]
+    fn test_syntax_error_has_position() {
+        let err =
+            validate_read_only("SELECT *\nFROM t WHERE )", &GuardConfig::default()).unwrap_err();
+        assert_eq!(err.rule.code(), "syntax_error");
+        assert!(!err.rule.to_string().contains("Line:"));
+        assert_eq!(err.span.map(|s| (s.line, s.column)), Some((2, 14)));
+    }
+}
@@ -0,0 +1,283 @@
+use std::ops::ControlFlow;
+
+use sqlparser::ast::{
+    visit_expressions_mut, visit_relations, visit_relations_mut, Expr, Ident, ObjectName, Query,
+    SelectItem, SetExpr, Value, VisitMut, VisitorMut,
+};
+
+use crate::chains::ChainRegistry;
+
+/// Makes sure `query` returns at most `max_rows + 1` rows, so the caller can tell whether the
+/// result was cut off by fetching one row past the cap.
+///
+/// A literal `LIMIT` at or below the cap is left alone. Limits given as expressions or
+/// placeholders, and `FETCH FIRST`, cannot be compared here; the caller still has to stop
+/// reading after `max_rows + 1` rows.
+pub fn apply_row_limit(query: &mut Query, max_rows: usize) {
+    if query.fetch.is_some() {
+        return;
+    }
+    let within_cap = match &query.limit {
+        None => false,
+        Some(Expr::Value(Value::Number(n, _))) => n.parse::<usize>().is_ok_and(|n| n <= max_rows),
+        Some(_) => return,
+    };
+    if !within_cap {
+        let sentinel = max_rows.saturating_add(1).to_string();
+        query.limit = Some(Expr::Value(Value::Number(sentinel, false)));
+    }
+}
+
+/// Points `<chain>.<table>` relations at the chain's indexed tables, as listed in the
+/// registry. Only table references are rewritten, along with column references spelled out as
+/// `<chain>.<table>.<column>` and wildcards as `<chain>.<table>.*`; string literals, aliases and
+/// other qualified names are left alone.
+pub fn map_chain_tables(query: &mut Query, chains: &ChainRegistry) {
+    let mut mapped: Vec<(ObjectName, ObjectName)> = Vec::new();
+    let _ = visit_relations_mut(query, |name| {
+        if let Some(indexed) = indexed_table(name, chains) {
+            mapped.push((name.clone(), indexed.clone()));
+            *name = indexed;
+        }
+        ControlFlow::<()>::Continue(())
+    });
+    if mapped.is_empty() {
+        return;
+    }
+    let _ = visit_expressions_mut(query, |expr| {
+        if let Expr::CompoundIdentifier(idents) = expr {
+            let table = mapped
+                .iter()
+                .find(|(original, _)| idents.len() > 2 && same_name(&original.0, &idents[..2]));
+            if let Some((_, indexed)) = table {
+                idents.splice(..2, indexed.0.iter().cloned());
+            }
+        }
+        ControlFlow::<()>::Continue(())
+    });
+    let _ = query.visit(&mut QualifiedWildcards(&mapped));
+}
+
+/// Rewrites `<chain>.<table>.*` in the select list of every query and subquery.
+struct QualifiedWildcards<'a>(&'a [(ObjectName, ObjectName)]);
+
+impl VisitorMut for QualifiedWildcards<'_> {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<()> {
+        self.map_select_items(&mut query.body);
+        ControlFlow::Continue(())
+    }
+}
+
+impl QualifiedWildcards<'_> {
+    fn map_select_items(&self, body: &mut SetExpr) {
+        match body {
+            SetExpr::Select(select) => {
+                for item in &mut select.projection {
+                    let SelectItem::QualifiedWildcard(name, _) = item else {
+                        continue;
+                    };
+                    let table = self
+                        .0
+                        .iter()
+                        .find(|(original, _)| same_name(&original.0, &name.0));
+                    if let Some((_, indexed)) = table {
+                        *name = indexed.clone();
+                    }
+                }
+            }
+            SetExpr::SetOperation { left, right, .. } => {
+                self.map_select_items(left);
+                self.map_select_items(right);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Canonical names of the chains whose indexed tables `query` reads, whether written as
+/// `<chain>.<table>` (before [`map_chain_tables`]) or by their indexed name.
+pub fn referenced_chains(query: &Query, chains: &ChainRegistry) -> Vec<String> {
+    let mut referenced = Vec::new();
+    let _ = visit_relations(query, |name| {
+        let chain = match name.0.as_slice() {
+            [chain, _] if chains.get(&chain.value).is_some() => chains.get(&chain.value),
+            [schema, table] => chains.chain_for_table(Some(&schema.value), &table.value),
+            [table] => chains.chain_for_table(None, &table.value),
+            _ => None,
+        };
+        if let Some(chain) = chain {
+            if !referenced.contains(&chain.name) {
+                referenced.push(chain.name.clone());
+            }
+        }
+        ControlFlow::<()>::Continue(())
+    });
+    referenced
+}
+
+fn indexed_table(name: &ObjectName, chains: &ChainRegistry) -> Option<ObjectName> {
+    let [chain, table] = name.0.as_slice() else {
+        return None;
+    };
+    let (schema, indexed) = chains.get(&chain.value)?.indexed_table(&table.value);
+    let table = Ident {
+        value: indexed,
+        quote_style: table.quote_style,
+    };
+    Some(ObjectName(match schema {
+        Some(schema) => vec![Ident::new(schema), table],
+        None => vec![table],
+    }))
+}
+
+fn same_name(a: &[Ident], b: &[Ident]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| match (a.quote_style, b.quote_style) {
+                (None, None) => a.value.eq_ignore_ascii_case(&b.value),
+                _ => a.value == b.value,
+            })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlparser::dialect::PostgreSqlDialect;
+    use sqlparser::parser::Parser;
+
+    fn parse(sql: &str) -> Query {
+        Parser::new(&PostgreSqlDialect {})
+            .try_with_sql(sql)
+            .and_then(|mut p| p.parse_query())
+            .unwrap()
+    }
+
+    fn limited(sql: &str, max_rows: usize) -> String {
+        let mut query = parse(sql);
+        apply_row_limit(&mut query, max_rows);
+        query.to_string()
+    }
+
+    fn mapped(sql: &str, registry: &ChainRegistry) -> String {
+        let mut query = parse(sql);
+        map_chain_tables(&mut query, registry);
+        query.to_string()
+    }
+
+    #[test]
+    fn test_limit_is_injected() {
+        assert_eq!(
+            limited("SELECT * FROM eth_logs", 100),
+            "SELECT * FROM eth_logs LIMIT 101"
+        );
+        assert_eq!(
+            limited("SELECT a FROM t UNION SELECT a FROM u ORDER BY a", 10),
+            "SELECT a FROM t UNION SELECT a FROM u ORDER BY a LIMIT 11"
+        );
+    }
+
+    #[test]
+    fn test_large_limit_is_clamped() {
+        assert_eq!(
+            limited("SELECT * FROM t LIMIT 5000 OFFSET 10", 100),
+            "SELECT * FROM t LIMIT 101 OFFSET 10"
+        );
+    }
+
+    #[test]
+    fn test_small_or_dynamic_limit_is_kept() {
+        assert_eq!(
+            limited("SELECT * FROM t LIMIT 5", 100),
+            "SELECT * FROM t LIMIT 5"
+        );
+        assert_eq!(
+            limited("SELECT * FROM t LIMIT $n", 100),
+            "SELECT * FROM t LIMIT $n"
+        );
+        assert_eq!(
+            limited("SELECT * FROM t FETCH FIRST 5000 ROWS ONLY", 100),
+            "SELECT * FROM t FETCH FIRST 5000 ROWS ONLY"
+        );
+    }
+
+    #[test]
+    fn test_only_outer_query_is_limited() {
+        assert_eq!(
+            limited("SELECT * FROM (SELECT * FROM t LIMIT 5000) s", 100),
+            "SELECT * FROM (SELECT * FROM t LIMIT 5000) AS s LIMIT 101"
+        );
+    }
+
+    #[test]
+    fn test_unbounded_cap_does_not_overflow() {
+        assert_eq!(
+            limited("SELECT * FROM t", usize::MAX),
+            format!("SELECT * FROM t LIMIT {}", usize::MAX)
+        );
+    }
+
+    #[test]
+    fn test_chain_tables_are_mapped() {
+        let chains = ChainRegistry::default();
+        assert_eq!(
+            mapped(
+                "SELECT eth.blocks.number, eth.hash, 'eth.blocks' FROM eth.blocks AS eth \
+                 JOIN (SELECT * FROM base.logs) l ON true WHERE x IN (SELECT id FROM public.t)",
+                &chains
+            ),
+            "SELECT eth_blocks.number, eth.hash, 'eth.blocks' FROM eth_blocks AS eth \
+             JOIN (SELECT * FROM base_logs) AS l ON true WHERE x IN (SELECT id FROM public.t)"
+        );
+        assert_eq!(
+            mapped("SELECT * FROM eth.blocks.extra, unknown.blocks", &chains),
+            "SELECT * FROM eth.blocks.extra, unknown.blocks"
+        );
+    }
+
+    #[test]
+    fn test_qualified_wildcards_are_mapped() {
+        let chains = ChainRegistry::default();
+        assert_eq!(
+            mapped(
+                "SELECT eth.blocks.*, l.* FROM eth.blocks JOIN base.logs l ON true \
+                 UNION ALL SELECT * FROM (SELECT base.logs.* FROM base.logs) s",
+                &chains
+            ),
+            "SELECT eth_blocks.*, l.* FROM eth_blocks JOIN base_logs AS l ON true \
+             UNION ALL SELECT * FROM (SELECT base_logs.* FROM base_logs) AS s"
+        );
+    }
+
+    #[test]
+    fn test_chain_tables_in_schemas() {
+        let chains = ChainRegistry::from_json(
+            r#"{"chains": [
+                {"name": "eth", "aliases": ["ethereum"], "engine": "eql", "schema": "eth"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            mapped(
+                "WITH b AS (SELECT * FROM ETH.blocks) SELECT * FROM b, ethereum.\"Logs\"",
+                &chains
+            ),
+            "WITH b AS (SELECT * FROM eth.blocks) SELECT * FROM b, eth.\"Logs\""
+        );
+    }
+
+    #[test]
+    fn test_referenced_chains() {
+        let chains = ChainRegistry::default();
+        let referenced = |sql| referenced_chains(&parse(sql), &chains);
+        assert_eq!(
+            referenced("SELECT * FROM eth.blocks JOIN base_logs ON true JOIN users ON true"),
+            ["eth", "base"]
+        );
+        let mut query = parse("SELECT * FROM (SELECT * FROM public.arb_txs) t, eth.blocks");
+        map_chain_tables(&mut query, &chains);
+        assert_eq!(referenced_chains(&query, &chains), ["arb", "eth"]);
+    }
+}
@@ -1,102 +1,562 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use base64::Engine;
+
 use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use serde::{Deserialize, Serialize};
 use serde_json::{self, Map, Value};
-use sqlx::any::{AnyRow, AnyTypeInfo, AnyTypeInfoKind};
-use sqlx::Decode;
+use sqlx::any::{Any, AnyColumn, AnyRow, AnyTypeInfo, AnyTypeInfoKind, AnyValueRef};
+use sqlx::error::BoxDynError;
+use sqlx::types::BigDecimal;
+use sqlx::{Decode, Describe};
 use sqlx::{Column, Row, TypeInfo, ValueRef};
 use log;
 
+use crate::pg_types;
+
+/// How NUMERIC and DECIMAL values are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NumericMode {
+    /// A JSON number when the nearest float prints back as the same value, otherwise a string
+    /// with every digit, so a uint256 balance is never rounded.
+    #[default]
+    Auto,
+    /// Always a string with every digit.
+    String,
+    /// Always a JSON number, rounded to the nearest float. Values beyond the float range are
+    /// left null with a decode warning.
+    Number,
+}
+
+/// How binary columns (BYTEA, BLOB, VARBINARY) are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BinaryEncoding {
+    /// Lowercase hex with a `0x` prefix, the way hashes and calldata are usually shown.
+    #[default]
+    Hex,
+    /// Standard base64 with padding.
+    Base64,
+}
+
+impl BinaryEncoding {
+    pub fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            BinaryEncoding::Hex => {
+                let mut hex = String::with_capacity(2 + bytes.len() * 2);
+                hex.push_str("0x");
+                for byte in bytes {
+                    let _ = write!(hex, "{byte:02x}");
+                }
+                hex
+            }
+            BinaryEncoding::Base64 => base64::engine::general_purpose::STANDARD.encode(bytes),
+        }
+    }
+}
+
+/// Options that change how values are written to JSON. The defaults are what
+/// [`row_to_json`] uses.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonOptions {
+    pub numeric: NumericMode,
+    /// Writes 64-bit integers outside ±(2^53 - 1) as strings, since JavaScript would round
+    /// them.
+    pub bigint_as_string: bool,
+    pub binary: BinaryEncoding,
+}
+
+/// A cell the driver returned but that could not be decoded as its type. The cell is written
+/// as null.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecodeError {
+    pub column: String,
+    /// The database's type name for the value.
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub message: String,
+}
+
+/// A [`DecodeError`] located in a result, as reported in the `warnings` of a response.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecodeWarning {
+    /// Zero-based index of the row in the result.
+    pub row: usize,
+    #[serde(flatten)]
+    pub error: DecodeError,
+}
+
+impl std::fmt::Display for DecodeWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let DecodeError {
+            column,
+            type_name,
+            message,
+        } = &self.error;
+        let row = self.row;
+        write!(f, "Could not decode column \"{column}\" ({type_name}) in row {row}: {message}")
+    }
+}
+
+/// The largest integer a JavaScript number holds exactly, 2^53 - 1.
+pub const MAX_SAFE_INTEGER: u64 = 9_007_199_254_740_991;
+
+fn is_safe_integer(value: i128) -> bool {
+    value.unsigned_abs() <= u128::from(MAX_SAFE_INTEGER)
+}
+
+fn integer_to_json<T: Into<i128> + Into<Value> + Copy>(value: T, options: &JsonOptions) -> Value {
+    let wide: i128 = value.into();
+    if options.bigint_as_string && !is_safe_integer(wide) {
+        wide.to_string().into()
+    } else {
+        value.into()
+    }
+}
+
+/// Replaces integers outside the JavaScript safe range with strings, anywhere in `value`. Used
+/// for `bigint_as_string` on RPC results, which arrive already serialized.
+pub fn stringify_unsafe_integers(value: &mut Value) {
+    match value {
+        Value::Number(n) => {
+            let unsafe_int = n
+                .as_i64()
+                .map(i128::from)
+                .or_else(|| n.as_u64().map(i128::from))
+                .filter(|&int| !is_safe_integer(int));
+            if let Some(int) = unsafe_int {
+                *value = int.to_string().into();
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(stringify_unsafe_integers),
+        Value::Object(map) => map.values_mut().for_each(stringify_unsafe_integers),
+        _ => {}
+    }
+}
+
 pub fn row_to_json(row: &AnyRow) -> Value {
+    row_to_json_with(row, &JsonOptions::default(), &mut Vec::new())
+}
+
+/// Encodes a row as an object. Cells that fail to decode are written as null and added to
+/// `errors`.
+pub fn row_to_json_with(
+    row: &AnyRow,
+    options: &JsonOptions,
+    errors: &mut Vec<DecodeError>,
+) -> Value {
     use Value::Object;
 
     let columns = row.columns();
     let mut map = Map::new();
     for col in columns {
         let key = col.name().to_string();
-        let value: Value = sql_to_json(row, col);
+        let value: Value = sql_to_json_or_null(row, col, options, errors);
         map = add_value_to_map(map, (key, value));
     }
     Object(map)
 }
 
+/// Like [`row_to_json_with`], but positional: one element per column in select-list order, so
+/// columns sharing a name stay apart.
+pub fn row_to_json_array(
+    row: &AnyRow,
+    options: &JsonOptions,
+    errors: &mut Vec<DecodeError>,
+) -> Value {
+    Value::Array(
+        row.columns()
+            .iter()
+            .map(|col| sql_to_json_or_null(row, col, options, errors))
+            .collect(),
+    )
+}
+
+/// [`sql_to_json_with`], recording a failure in `errors` and writing null in its place.
+pub fn sql_to_json_or_null(
+    row: &AnyRow,
+    col: &AnyColumn,
+    options: &JsonOptions,
+    errors: &mut Vec<DecodeError>,
+) -> Value {
+    sql_to_json_with(row, col, options).unwrap_or_else(|e| {
+        log::warn!("Failed to decode column {:?} ({}): {}", e.column, e.type_name, e.message);
+        errors.push(e);
+        Value::Null
+    })
+}
+
+/// One entry of the `columns` block in the indexed response.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColumnMeta {
+    pub name: String,
+    pub ordinal: usize,
+    /// The database's own type name, e.g. `INT8` or `TIMESTAMPTZ`.
+    #[serde(rename = "type")]
+    pub type_name: String,
+    /// Reported for empty results, whose columns come from the driver's description of the
+    /// statement. `None` otherwise, and for most computed columns.
+    pub nullable: Option<bool>,
+    /// How values of this column appear in the JSON rows.
+    pub json: &'static str,
+}
+
+/// Describes the result columns, in select-list order. The first row is the primary source
+/// since value types are what [`sql_nonnull_to_json`] goes by; `describe` lists the columns
+/// when no rows came back.
+pub fn columns_meta(
+    rows: &[AnyRow],
+    describe: Option<&Describe<Any>>,
+    options: &JsonOptions,
+) -> Vec<ColumnMeta> {
+    let nullable = |ordinal| describe.and_then(|d| d.nullable(ordinal));
+    if let Some(first) = rows.first() {
+        return first
+            .columns()
+            .iter()
+            .map(|col| {
+                let (kind, type_name) = column_type(rows, col);
+                ColumnMeta {
+                    name: col.name().to_string(),
+                    ordinal: col.ordinal(),
+                    type_name,
+                    nullable: nullable(col.ordinal()),
+                    json: kind.json_type(options),
+                }
+            })
+            .collect();
+    }
+    describe
+        .map(|d| d.columns())
+        .unwrap_or_default()
+        .iter()
+        .map(|col| ColumnMeta {
+            name: col.name().to_string(),
+            ordinal: col.ordinal(),
+            type_name: col.type_info().name().to_string(),
+            nullable: nullable(col.ordinal()),
+            json: value_kind(col.type_info()).json_type(options),
+        })
+        .collect()
+}
+
+/// The kind of the first non-null value in the column. Some drivers (SQLite in particular)
+/// only type values, not result columns, so the column's own type info is a fallback.
+pub fn column_kind(rows: &[AnyRow], col: &AnyColumn) -> ValueKind {
+    column_type(rows, col).0
+}
+
+fn column_type(rows: &[AnyRow], col: &AnyColumn) -> (ValueKind, String) {
+    rows.iter()
+        .filter_map(|row| row.try_get_raw(col.ordinal()).ok())
+        .find(|value| !value.is_null())
+        .map(|value| {
+            let type_info = value.type_info();
+            (value_kind(&type_info), type_info.name().to_string())
+        })
+        .unwrap_or_else(|| {
+            let type_info = col.type_info();
+            (value_kind(type_info), type_info.name().to_string())
+        })
+}
+
 pub fn sql_to_json(row: &AnyRow, col: &sqlx::any::AnyColumn) -> Value {
-    let raw_value_result = row.try_get_raw(col.ordinal());
-    match raw_value_result {
+    sql_to_json_or_null(row, col, &JsonOptions::default(), &mut Vec::new())
+}
+
+pub fn sql_to_json_with(
+    row: &AnyRow,
+    col: &AnyColumn,
+    options: &JsonOptions,
+) -> Result<Value, DecodeError> {
+    let decode_error = |type_name: &str, e: BoxDynError| DecodeError {
+        column: col.name().to_string(),
+        type_name: type_name.to_string(),
+        message: e.to_string(),
+    };
+    match row.try_get_raw(col.ordinal()) {
         Ok(raw_value) if !raw_value.is_null() => {
+            let type_name = raw_value.type_info().name().to_string();
             let mut raw_value = Some(raw_value);
-            let decoded = sql_nonnull_to_json(|| {
-                raw_value
-                    .take()
-                    .unwrap_or_else(|| row.try_get_raw(col.ordinal()).unwrap())
-            });
+            let decoded = sql_nonnull_to_json_with(
+                || {
+                    raw_value
+                        .take()
+                        .unwrap_or_else(|| row.try_get_raw(col.ordinal()).unwrap())
+                },
+                options,
+            )
+            .map_err(|e| decode_error(&type_name, e))?;
             log::trace!("Decoded value: {decoded:?}");
-            decoded
-        }
-        Ok(_null) => Value::Null,
-        Err(e) => {
-            log::warn!("Unable to extract value from row: {e:?}");
-            Value::Null
+            Ok(decoded)
         }
+        Ok(_null) => Ok(Value::Null),
+        Err(e) => Err(decode_error(col.type_info().name(), e.into())),
     }
 }
 
-fn decode_raw<'a, T: Decode<'a, sqlx::any::Any> + Default>(
-    raw_value: sqlx::any::AnyValueRef<'a>,
-) -> T {
-    match T::decode(raw_value) {
-        Ok(v) => v,
-        Err(e) => {
-            let type_name = std::any::type_name::<T>();
-            log::error!("Failed to decode {type_name} value: {e}");
-            T::default()
+/// The Rust type a column is decoded through. Shared by the JSON and the columnar encoders so
+/// both read a given SQL type the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Float,
+    Decimal,
+    BigInt,
+    Int,
+    SmallInt,
+    BigUnsigned,
+    Unsigned,
+    Bool,
+    Date,
+    Time,
+    Timestamp,
+    DateTime,
+    Json,
+    Binary,
+    Uuid,
+    Interval,
+    Network,
+    Array,
+    Text,
+}
+
+impl ValueKind {
+    /// The JSON type values of this kind are written as; `json` means any JSON value.
+    pub fn json_type(self, options: &JsonOptions) -> &'static str {
+        match self {
+            ValueKind::Decimal => match options.numeric {
+                NumericMode::Auto => "number|string",
+                NumericMode::String => "string",
+                NumericMode::Number => "number",
+            },
+            ValueKind::BigInt | ValueKind::BigUnsigned if options.bigint_as_string => {
+                "number|string"
+            }
+            ValueKind::Float
+            | ValueKind::BigInt
+            | ValueKind::Int
+            | ValueKind::SmallInt
+            | ValueKind::BigUnsigned
+            | ValueKind::Unsigned => "number",
+            ValueKind::Bool => "boolean",
+            ValueKind::Json => "json",
+            ValueKind::Interval => "object",
+            ValueKind::Array => "array",
+            ValueKind::Date
+            | ValueKind::Time
+            | ValueKind::Timestamp
+            | ValueKind::DateTime
+            | ValueKind::Binary
+            | ValueKind::Uuid
+            | ValueKind::Network
+            | ValueKind::Text => "string",
         }
     }
 }
 
-pub fn sql_nonnull_to_json<'r>(mut get_ref: impl FnMut() -> sqlx::any::AnyValueRef<'r>) -> Value {
-    let raw_value = get_ref();
-    let type_info = raw_value.type_info();
-    let type_name = type_info.name();
-    log::trace!("Decoding a value of type {type_name:?} (type info: {type_info:?})");
-    match type_name {
-        "REAL" | "FLOAT" | "FLOAT4" | "FLOAT8" | "DOUBLE" | "NUMERIC" | "DECIMAL" => {
-            decode_raw::<f64>(raw_value).into()
+pub fn value_kind(type_info: &AnyTypeInfo) -> ValueKind {
+    match type_info.name() {
+        "BIT" if matches!(*type_info, AnyTypeInfo(AnyTypeInfoKind::Mssql(_))) => ValueKind::Bool,
+        "BIT" if matches!(*type_info, AnyTypeInfo(AnyTypeInfoKind::MySql(ref mysql_type)) if mysql_type.max_size() == Some(1)) => {
+            ValueKind::Bool
+        }
+        "BIT" if matches!(*type_info, AnyTypeInfo(AnyTypeInfoKind::MySql(_))) => {
+            ValueKind::BigUnsigned
         }
+        name => value_kind_by_name(name),
+    }
+}
+
+/// The kind for a type name alone, which is all Postgres gives for the elements of an array.
+fn value_kind_by_name(name: &str) -> ValueKind {
+    match name {
+        "REAL" | "FLOAT" | "FLOAT4" | "FLOAT8" | "DOUBLE" => ValueKind::Float,
+        "NUMERIC" | "DECIMAL" => ValueKind::Decimal,
         "INT8" | "BIGINT" | "SERIAL8" | "BIGSERIAL" | "IDENTITY" | "INT64" | "INTEGER8"
-        | "BIGINT SIGNED" => decode_raw::<i64>(raw_value).into(),
-        "INT" | "INT4" | "INTEGER" | "MEDIUMINT" | "YEAR" => decode_raw::<i32>(raw_value).into(),
-        "INT2" | "SMALLINT" | "TINYINT" => decode_raw::<i16>(raw_value).into(),
-        "BIGINT UNSIGNED" => decode_raw::<u64>(raw_value).into(),
+        | "BIGINT SIGNED" => ValueKind::BigInt,
+        "INT" | "INT4" | "INTEGER" | "MEDIUMINT" | "YEAR" => ValueKind::Int,
+        "INT2" | "SMALLINT" | "TINYINT" => ValueKind::SmallInt,
+        "BIGINT UNSIGNED" => ValueKind::BigUnsigned,
         "INT UNSIGNED" | "MEDIUMINT UNSIGNED" | "SMALLINT UNSIGNED" | "TINYINT UNSIGNED" => {
-            decode_raw::<u32>(raw_value).into()
-        }
-        "BOOL" | "BOOLEAN" => decode_raw::<bool>(raw_value).into(),
-        "BIT" if matches!(*type_info, AnyTypeInfo(AnyTypeInfoKind::Mssql(_))) => {
-            decode_raw::<bool>(raw_value).into()
-        }
-        "BIT" if matches!(*type_info, AnyTypeInfo(AnyTypeInfoKind::MySql(ref mysql_type)) if mysql_type.max_size() == Some(1)) => {
-            decode_raw::<bool>(raw_value).into()
+            ValueKind::Unsigned
         }
-        "BIT" if matches!(*type_info, AnyTypeInfo(AnyTypeInfoKind::MySql(_))) => {
-            decode_raw::<u64>(raw_value).into()
+        "BOOL" | "BOOLEAN" => ValueKind::Bool,
+        "DATE" => ValueKind::Date,
+        "TIME" | "TIMETZ" => ValueKind::Time,
+        "DATETIMEOFFSET" | "TIMESTAMP" | "TIMESTAMPTZ" => ValueKind::Timestamp,
+        "DATETIME" | "DATETIME2" => ValueKind::DateTime,
+        "JSON" | "JSON[]" | "JSONB" | "JSONB[]" => ValueKind::Json,
+        "BYTEA" | "BLOB" | "TINYBLOB" | "MEDIUMBLOB" | "LONGBLOB" | "BINARY" | "VARBINARY"
+        | "IMAGE" => ValueKind::Binary,
+        "UUID" => ValueKind::Uuid,
+        "INTERVAL" => ValueKind::Interval,
+        "INET" | "CIDR" | "MACADDR" | "MACADDR8" => ValueKind::Network,
+        name if name.ends_with("[]") => ValueKind::Array,
+        // Deserialize as a string by default
+        _ => ValueKind::Text,
+    }
+}
+
+pub fn sql_nonnull_to_json<'r>(
+    get_ref: impl FnMut() -> sqlx::any::AnyValueRef<'r>,
+) -> Result<Value, BoxDynError> {
+    sql_nonnull_to_json_with(get_ref, &JsonOptions::default())
+}
+
+pub fn sql_nonnull_to_json_with<'r>(
+    mut get_ref: impl FnMut() -> AnyValueRef<'r>,
+    options: &JsonOptions,
+) -> Result<Value, BoxDynError> {
+    let raw_value = get_ref();
+    let type_info = raw_value.type_info();
+    log::trace!("Decoding a value of type {:?} (type info: {type_info:?})", type_info.name());
+    Ok(match value_kind(&type_info) {
+        ValueKind::Array => {
+            let element = value_kind_by_name(type_info.name().trim_end_matches("[]"));
+            let text = String::decode(raw_value)?;
+            pg_types::parse_array(&text, |item| text_to_json(element, item, options))
+                .unwrap_or_else(|| {
+                    log::warn!("Unrecognized array literal, returning it as text: {text}");
+                    text.into()
+                })
         }
-        "DATE" => decode_raw::<chrono::NaiveDate>(raw_value)
-            .to_string()
+        ValueKind::Float => f64::decode(raw_value)?.into(),
+        ValueKind::Decimal => match decode_decimal(raw_value, get_ref)? {
+            Some(value) => match decimal_to_json(&value, options) {
+                Value::Null => return Err(format!("{value} is beyond the range of a float").into()),
+                json => json,
+            },
+            None => Value::Null,
+        },
+        ValueKind::BigInt => integer_to_json(i64::decode(raw_value)?, options),
+        ValueKind::Int => i32::decode(raw_value)?.into(),
+        ValueKind::SmallInt => i16::decode(raw_value)?.into(),
+        ValueKind::BigUnsigned => integer_to_json(u64::decode(raw_value)?, options),
+        ValueKind::Unsigned => u32::decode(raw_value)?.into(),
+        ValueKind::Bool => bool::decode(raw_value)?.into(),
+        ValueKind::Date => chrono::NaiveDate::decode(raw_value)?.to_string().into(),
+        ValueKind::Time => chrono::NaiveTime::decode(raw_value)?.to_string().into(),
+        ValueKind::Timestamp => DateTime::<FixedOffset>::decode(raw_value)?
+            .to_rfc3339()
             .into(),
-        "TIME" | "TIMETZ" => decode_raw::<chrono::NaiveTime>(raw_value)
-            .to_string()
-            .into(),
-        "DATETIMEOFFSET" | "TIMESTAMP" | "TIMESTAMPTZ" => {
-            decode_raw::<DateTime<FixedOffset>>(raw_value)
-                .to_rfc3339()
-                .into()
-        }
-        "DATETIME" | "DATETIME2" => decode_raw::<NaiveDateTime>(raw_value)
+        ValueKind::DateTime => NaiveDateTime::decode(raw_value)?
             .format("%FT%T%.f")
             .to_string()
             .into(),
-        "JSON" | "JSON[]" | "JSONB" | "JSONB[]" => decode_raw::<Value>(raw_value),
-        // Deserialize as a string by default
-        _ => decode_raw::<String>(raw_value).into(),
+        ValueKind::Json => Value::decode(raw_value)?,
+        ValueKind::Binary => options.binary.encode(&Vec::<u8>::decode(raw_value)?).into(),
+        ValueKind::Uuid => match String::decode(raw_value) {
+            Ok(text) => text.into(),
+            Err(_) => pg_types::uuid_from_bytes(&Vec::<u8>::decode(get_ref())?)
+                .ok_or("expected a UUID as text or as 16 bytes")?
+                .into(),
+        },
+        ValueKind::Interval => interval_to_json(String::decode(raw_value)?),
+        ValueKind::Network | ValueKind::Text => String::decode(raw_value)?.into(),
+    })
+}
+
+/// An interval as its ISO-8601 duration next to the text the database printed, which keeps
+/// styles the conversion does not understand readable. `iso` is null for those.
+fn interval_to_json(raw: String) -> Value {
+    serde_json::json!({ "iso": pg_types::interval_to_iso8601(&raw), "raw": raw })
+}
+
+/// Converts one element of a Postgres array literal, which arrives in the type's text form.
+/// Elements that do not parse as their type are kept as strings.
+fn text_to_json(kind: ValueKind, text: &str, options: &JsonOptions) -> Value {
+    let parsed = match kind {
+        ValueKind::Float => text.parse::<f64>().ok().map(Value::from),
+        ValueKind::Decimal => {
+            let value = BigDecimal::from_str(text).ok();
+            // Values beyond the float range stay text, like anything else that does not parse.
+            value.map(|value| decimal_to_json(&value, options)).filter(|json| !json.is_null())
+        }
+        ValueKind::BigInt | ValueKind::Int | ValueKind::SmallInt => {
+            text.parse::<i64>().ok().map(|value| integer_to_json(value, options))
+        }
+        ValueKind::BigUnsigned | ValueKind::Unsigned => {
+            text.parse::<u64>().ok().map(|value| integer_to_json(value, options))
+        }
+        ValueKind::Bool => match text {
+            "t" | "true" => Some(true.into()),
+            "f" | "false" => Some(false.into()),
+            _ => None,
+        },
+        ValueKind::Timestamp => DateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f%#z")
+            .or_else(|_| {
+                NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f")
+                    .map(|value| value.and_utc().fixed_offset())
+            })
+            .ok()
+            .map(|value| value.to_rfc3339().into()),
+        ValueKind::Json => serde_json::from_str(text).ok(),
+        ValueKind::Binary => text
+            .strip_prefix("\\x")
+            .and_then(|hex| {
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+                    .collect::<Option<Vec<u8>>>()
+            })
+            .map(|bytes| options.binary.encode(&bytes).into()),
+        ValueKind::Interval => Some(interval_to_json(text.to_string())),
+        ValueKind::Date
+        | ValueKind::Time
+        | ValueKind::DateTime
+        | ValueKind::Uuid
+        | ValueKind::Network
+        | ValueKind::Array
+        | ValueKind::Text => None,
+    };
+    parsed.unwrap_or_else(|| text.into())
+}
+
+/// Decodes a NUMERIC or DECIMAL value exactly. Drivers that hand these out as floats (SQLite
+/// has no decimal storage) get the float's shortest representation instead. `None` for NaN
+/// and infinities, which have no decimal form.
+pub fn decode_decimal<'r>(
+    raw_value: AnyValueRef<'r>,
+    mut get_ref: impl FnMut() -> AnyValueRef<'r>,
+) -> Result<Option<BigDecimal>, BoxDynError> {
+    match BigDecimal::decode(raw_value) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) => {
+            log::trace!("Decoding decimal as a float instead: {e}");
+            let float = f64::decode(get_ref())?;
+            Ok(BigDecimal::from_str(&float.to_string()).ok())
+        }
+    }
+}
+
+/// `null` only under [`NumericMode::Number`], for values beyond the float range.
+pub fn decimal_to_json(value: &BigDecimal, options: &JsonOptions) -> Value {
+    let mode = options.numeric;
+    if mode != NumericMode::String && value.is_integer() {
+        let (digits, _) = value.with_scale(0).into_bigint_and_exponent();
+        // Under `Auto`, integers a float cannot hold exactly go through the round trip below.
+        match i64::try_from(&digits) {
+            Ok(int) if mode == NumericMode::Number || is_safe_integer(int.into()) => {
+                return integer_to_json(int, options);
+            }
+            _ => {}
+        }
+    }
+    let exact = value.to_string();
+    // BigDecimal::to_f64 scales an integer by a power of ten and can be off by one ulp;
+    // parsing the digits rounds correctly.
+    let float = exact.parse::<f64>().ok().filter(|f| f.is_finite());
+    match mode {
+        NumericMode::String => exact.into(),
+        NumericMode::Number => float.into(),
+        NumericMode::Auto => match float {
+            Some(f) if BigDecimal::from_str(&f.to_string()).is_ok_and(|back| back == *value) => {
+                f.into()
+            }
+            _ => exact.into(),
+        },
     }
 }
 
@@ -196,6 +656,72 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_row_to_json_array() -> anyhow::Result<()> {
+        let db_url = test_database_url();
+        let mut c = sqlx::AnyConnection::connect(&db_url).await?;
+        let row = sqlx::query("SELECT 1 as id, 2 as id, 'x' as name")
+            .fetch_one(&mut c)
+            .await?;
+        let mut errors = Vec::new();
+        assert_eq!(
+            row_to_json_array(&row, &JsonOptions::default(), &mut errors),
+            serde_json::json!([1, 2, "x"])
+        );
+        assert!(errors.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_warning() {
+        let warning = DecodeWarning {
+            row: 3,
+            error: DecodeError {
+                column: "location".to_string(),
+                type_name: "POINT".to_string(),
+                message: "invalid utf-8 sequence".to_string(),
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&warning).unwrap(),
+            serde_json::json!({
+                "row": 3,
+                "column": "location",
+                "type": "POINT",
+                "message": "invalid utf-8 sequence"
+            })
+        );
+        assert_eq!(
+            warning.to_string(),
+            "Could not decode column \"location\" (POINT) in row 3: invalid utf-8 sequence"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_columns_meta() -> anyhow::Result<()> {
+        use sqlx::Executor;
+        let db_url = test_database_url();
+        let mut c = sqlx::AnyConnection::connect(&db_url).await?;
+        let sql = "SELECT 1 as id, 'x' as name";
+        let rows = sqlx::query(sql).fetch_all(&mut c).await?;
+        let options = JsonOptions::default();
+        let meta = columns_meta(&rows, None, &options);
+        let columns: Vec<(&str, usize, &str)> = meta
+            .iter()
+            .map(|col| (col.name.as_str(), col.ordinal, col.json))
+            .collect();
+        assert_eq!(columns, [("id", 0, "number"), ("name", 1, "string")]);
+
+        // Without rows the column list comes from describing the statement.
+        let describe = (&mut c).describe(sql).await?;
+        let names: Vec<String> = columns_meta(&[], Some(&describe), &options)
+            .into_iter()
+            .map(|col| col.name)
+            .collect();
+        assert_eq!(names, ["id", "name"]);
+        Ok(())
+    }
+
     #[tokio::test] 
     async fn test_postgres_types() -> anyhow::Result<()> {
         let Some(db_url) = db_specific_test("postgres") else {
@@ -220,7 +746,21 @@ mod tests {
                 '{\"key\": \"value\"}'::JSON as json,
                 '{\"key\": \"value\"}'::JSONB as jsonb,
                 age('2024-03-14'::timestamp, '2024-01-01'::timestamp) as age_interval,
-                justify_interval(interval '1 year 2 months 3 days') as justified_interval",
+                justify_interval(interval '1 year 2 months 3 days') as justified_interval,
+                123.45::NUMERIC as numeric,
+                (2::NUMERIC ^ 256 - 1)::NUMERIC(78, 0) as uint256_max,
+                9007199254740993::NUMERIC as unsafe_integer,
+                '{1,2,NULL}'::INT8[] as int_array,
+                ARRAY['a b', NULL, 'c\"d'] as text_array,
+                '{{1,2},{3,4}}'::INT4[] as nested_array,
+                '{1.5,12345678901234567890.123}'::NUMERIC[] as numeric_array,
+                '{t,f}'::BOOL[] as bool_array,
+                ARRAY[INTERVAL '90 minutes'] as interval_array,
+                '-1 days +02:03:04.5'::INTERVAL as negative_interval,
+                'a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11'::UUID as uuid,
+                '192.168.1.10/24'::INET as inet,
+                '10.0.0.0/8'::CIDR as cidr,
+                '08:00:2b:01:02:03'::MACADDR as macaddr",
         )
         .fetch_one(&mut c)
         .await?;
@@ -238,13 +778,27 @@ mod tests {
                 "time": "13:14:15",
                 "timestamp": "2024-03-14T13:14:15+00:00",
                 "timestamptz": "2024-03-14T11:14:15+00:00",
-                "complex_interval": "1 year 2 mons 3 days",
-                "hour_interval": "04:00:00",
-                "fractional_interval": "1 day 12:00:00",
+                "complex_interval": {"iso": "P1Y2M3D", "raw": "1 year 2 mons 3 days"},
+                "hour_interval": {"iso": "PT4H", "raw": "04:00:00"},
+                "fractional_interval": {"iso": "P1DT12H", "raw": "1 day 12:00:00"},
                 "json": {"key": "value"},
                 "jsonb": {"key": "value"},
-                "age_interval": "2 mons 13 days",
-                "justified_interval": "1 year 2 mons 3 days"
+                "age_interval": {"iso": "P2M13D", "raw": "2 mons 13 days"},
+                "justified_interval": {"iso": "P1Y2M3D", "raw": "1 year 2 mons 3 days"},
+                "numeric": 123.45,
+                "uint256_max": "115792089237316195423570985008687907853269984665640564039457584007913129639935",
+                "unsafe_integer": "9007199254740993",
+                "int_array": [1, 2, null],
+                "text_array": ["a b", null, "c\"d"],
+                "nested_array": [[1, 2], [3, 4]],
+                "numeric_array": [1.5, "12345678901234567890.123"],
+                "bool_array": [true, false],
+                "interval_array": [{"iso": "PT1H30M", "raw": "01:30:00"}],
+                "negative_interval": {"iso": "P-1DT2H3M4.5S", "raw": "-1 days +02:03:04.5"},
+                "uuid": "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11",
+                "inet": "192.168.1.10/24",
+                "cidr": "10.0.0.0/8",
+                "macaddr": "08:00:2b:01:02:03"
             }),
         );
         Ok(())
@@ -369,7 +923,7 @@ mod tests {
                 "integer": 42,
                 "real": 42.25,
                 "string": "xxx",
-                "blob": "hello world",
+                "blob": "0x68656c6c6f20776f726c64",
             }),
         );
         Ok(())
@@ -429,6 +983,114 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_decimal_to_json() {
+        let decimal = |s: &str| BigDecimal::from_str(s).unwrap();
+        let mode = |numeric| JsonOptions {
+            numeric,
+            ..JsonOptions::default()
+        };
+        let auto = mode(NumericMode::Auto);
+        let wei = decimal("1234567890123456789012345678");
+        assert_eq!(decimal_to_json(&decimal("123.45"), &auto), 123.45);
+        assert_eq!(
+            decimal_to_json(&decimal("-42.00"), &auto),
+            serde_json::json!(-42)
+        );
+        assert_eq!(
+            decimal_to_json(&wei, &auto),
+            "1234567890123456789012345678"
+        );
+        assert_eq!(
+            decimal_to_json(&decimal("9007199254740993"), &auto),
+            "9007199254740993"
+        );
+        assert_eq!(
+            decimal_to_json(&decimal("-9007199254740991"), &auto),
+            serde_json::json!(-9_007_199_254_740_991i64)
+        );
+        assert_eq!(
+            decimal_to_json(&decimal("9007199254740993"), &mode(NumericMode::Number)),
+            serde_json::json!(9_007_199_254_740_993i64)
+        );
+        assert_eq!(
+            decimal_to_json(&decimal("0.1000000000000000000001"), &auto),
+            "0.1000000000000000000001"
+        );
+        assert_eq!(
+            decimal_to_json(&decimal("123.450"), &mode(NumericMode::String)),
+            "123.450"
+        );
+        assert_eq!(
+            decimal_to_json(&wei, &mode(NumericMode::Number)),
+            1.2345678901234568e27
+        );
+        assert_eq!(
+            decimal_to_json(&decimal("1e400"), &mode(NumericMode::Number)),
+            Value::Null
+        );
+        assert_eq!(
+            text_to_json(ValueKind::Decimal, "1e400", &mode(NumericMode::Number)),
+            "1e400"
+        );
+    }
+
+    #[test]
+    fn test_binary_encoding() {
+        let hash = [0xde, 0xad, 0x00, 0x0f];
+        assert_eq!(BinaryEncoding::Hex.encode(&hash), "0xdead000f");
+        assert_eq!(BinaryEncoding::Hex.encode(&[]), "0x");
+        assert_eq!(BinaryEncoding::Base64.encode(&hash), "3q0ADw==");
+    }
+
+    #[test]
+    fn test_array_elements() {
+        let options = JsonOptions::default();
+        let element = |kind, text| text_to_json(kind, text, &options);
+        assert_eq!(element(ValueKind::Int, "-7"), serde_json::json!(-7));
+        assert_eq!(element(ValueKind::Float, "0.5"), serde_json::json!(0.5));
+        assert_eq!(element(ValueKind::Bool, "t"), serde_json::json!(true));
+        assert_eq!(element(ValueKind::Binary, "\\x00ff"), serde_json::json!("0x00ff"));
+        assert_eq!(
+            element(ValueKind::Timestamp, "2024-03-14 13:14:15+02"),
+            serde_json::json!("2024-03-14T13:14:15+02:00")
+        );
+        assert_eq!(element(ValueKind::Json, r#"{"a":1}"#), serde_json::json!({"a": 1}));
+        assert_eq!(element(ValueKind::Int, "oops"), serde_json::json!("oops"));
+        assert_eq!(
+            value_kind_by_name("TEXT[]"),
+            ValueKind::Array,
+            "array types are recognized by their suffix"
+        );
+        assert_eq!(value_kind_by_name("JSONB[]"), ValueKind::Json);
+    }
+
+    #[test]
+    fn test_bigint_as_string() {
+        let options = JsonOptions {
+            bigint_as_string: true,
+            ..JsonOptions::default()
+        };
+        assert_eq!(integer_to_json(MAX_SAFE_INTEGER, &options), 9_007_199_254_740_991u64);
+        assert_eq!(integer_to_json(i64::MIN, &options), "-9223372036854775808");
+        assert_eq!(integer_to_json(u64::MAX, &options), "18446744073709551615");
+        assert_eq!(
+            integer_to_json(u64::MAX, &JsonOptions::default()),
+            18446744073709551615u64
+        );
+        assert_eq!(
+            decimal_to_json(&BigDecimal::from_str("9007199254740993").unwrap(), &options),
+            "9007199254740993"
+        );
+
+        let mut rpc = serde_json::json!({"number": 18_000_000, "gas": [u64::MAX, 1.5]});
+        stringify_unsafe_integers(&mut rpc);
+        assert_eq!(
+            rpc,
+            serde_json::json!({"number": 18_000_000, "gas": ["18446744073709551615", 1.5]})
+        );
+    }
+
     fn expect_json_object_equal(actual: &Value, expected: &Value) {
         use std::fmt::Write;
 
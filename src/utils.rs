@@ -1,4 +1,3 @@
-use regex::Regex;
 use rocket::{
     http::Status,
     response::{content::RawJson, status},
@@ -6,140 +5,85 @@ use rocket::{
 
 use serde::Serialize;
 use serde_json::json;
-use std:: collections::HashSet;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::time::Duration;
 
 
+/// Removes `--` and `//` line comments and `/* */` block comments, keeping the line break
+/// that ends a line comment. String literals and quoted identifiers are left alone, so a
+/// `'https://…'` or `'a--b'` value stays whole.
 pub fn remove_sql_comments(sql: &str) -> String {
-    // Regexes for each comment style
-    // 1. Block comments: /* ... */
-    let re_block = Regex::new(r"/\*[\s\S]*?\*/").unwrap();
-    // 2. -- single-line comments
-    let re_line1 = Regex::new(r"--[^\r\n]*").unwrap();
-    // 3. // single-line comments
-    let re_line2 = Regex::new(r"//[^\r\n]*").unwrap();
-
-    // Apply in order: block first, then single-line
-    let no_block = re_block.replace_all(sql, "");
-    let no_line1 = re_line1.replace_all(&no_block, "");
-    let no_line2 = re_line2.replace_all(&no_line1, "");
-
-    no_line2.into_owned()
-}
-
- const _BLACKLIST: &[&str] = &[
-        // DML
-        "INSERT", "UPDATE", "DELETE", "MERGE", "UPSERT", "TRUNCATE", "RETURNING", "OVERRIDING SYSTEM VALUE",
-
-        // DDL
-        "CREATE", "ALTER", "DROP", "RENAME", "COMMENT", "REINDEX", "CLUSTER", "DISCARD",
-
-        // Transactions
-        "BEGIN", "COMMIT", "ROLLBACK", "SAVEPOINT", "RELEASE", "PREPARE", "DEALLOCATE",
-
-        // Roles & Users
-        "GRANT", "REVOKE", "CREATE USER", "DROP USER", "CREATE ROLE", "DROP ROLE", "ALTER USER", "ALTER ROLE",
-        "SET ROLE", "RESET ROLE", "SESSION AUTHORIZATION", "SET SESSION AUTHORIZATION", "LOGIN", "PASSWORD",
-
-        // Schema & Object Management
-        "CREATE TABLE", "DROP TABLE", "ALTER TABLE", "UNLOGGED", "TEMP TABLE", "TEMPORARY",
-        "CREATE SEQUENCE", "ALTER SEQUENCE", "DROP SEQUENCE",
-        "CREATE VIEW", "DROP VIEW", "ALTER VIEW", "MATERIALIZED", "REFRESH MATERIALIZED VIEW",
-        "CREATE FUNCTION", "ALTER FUNCTION", "DROP FUNCTION",
-        "CREATE PROCEDURE", "DROP PROCEDURE", "CALL",
-        "CREATE TRIGGER", "DROP TRIGGER",
-        "CREATE RULE", "DROP RULE",
-        "CREATE INDEX", "DROP INDEX", "USING BTREE", "USING GIN", "USING HASH",
-        "CREATE EXTENSION", "ALTER EXTENSION", "DROP EXTENSION",
-        "CREATE SCHEMA", "DROP SCHEMA", "ALTER SCHEMA",
-
-        // Copy & External
-        "COPY", "DO", "LISTEN", "NOTIFY", "UNLISTEN", "EXPLAIN", "ANALYZE",
-
-        // Runtime and system information
-        "SHOW", "SET", "RESET", "CONFIG", "LOAD", "VACUUM", "ANALYZE", "CHECKPOINT", "REASSIGN OWNED",
-        "pg_sleep", "pg_cancel_backend", "pg_terminate_backend", "pg_reload_conf", "pg_rotate_logfile",
-        "pg_stat_reset", "pg_logical_emit_message",
-
-        // Low-level system functions
-        "pg_backend_pid", "pg_postmaster_start_time", "pg_current_xact_id", "txid_current",
-        "pg_is_in_recovery", "pg_last_xact_replay_timestamp", "pg_switch_wal", "pg_create_physical_replication_slot",
-        "pg_drop_replication_slot", "pg_create_logical_replication_slot", "pg_drop_logical_replication_slot",
-
-        // WAL, Replication
-        "pg_current_wal_lsn", "pg_wal_lsn_diff", "pg_replication_origin", "pg_create_restore_point",
-        "pg_start_backup", "pg_stop_backup", "pg_promote",
-
-        // System views/tables
-        "pg_stat_", "pg_replication_", "pg_settings", "pg_file_", "pg_ls_", "pg_log_", "pg_read_file",
-        "pg_read_binary_file", "pg_stat_file", "pg_tablespace", "pg_database", "pg_user", "pg_roles",
-        "pg_shadow", "pg_authid", "pg_auth_members", "pg_group",
-
-        // Size & config introspection
-        "pg_size_pretty", "pg_table_size", "pg_database_size", "pg_indexes_size",
-        "pg_total_relation_size", "pg_column_size", "pg_relation_size",
-
-        // Network/system
-        "inet_client_addr", "inet_client_port", "inet_server_addr", "inet_server_port",
-        "pg_hba_file_rules", "pg_ident_file_mappings",
-
-        // Injection & abuse patterns
-        "--", "/*", "*/", "#", ";", ";--", "OR 1=1", "' OR '1'='1", "\" OR \"1\"=\"1", "UNION SELECT",
-        "INFORMATION_SCHEMA", "SYSTEM_USER", "CURRENT_CATALOG", "CURRENT_SCHEMA",
-
-        // Encoding & internal config
-        "client_encoding", "application_name", "standard_conforming_strings",
-        "statement_timeout", "idle_in_transaction_session_timeout", "log_min_duration_statement",
-        "work_mem", "maintenance_work_mem", "shared_buffers", "effective_cache_size",
-
-        // User identity
-        "user", "current_user", "session_user", "system_user", "is_superuser",
-
-        // Time/locale
-        "datestyle", "timezone",
-
-        // Extensions or plugins
-        "plpgsql", "pgcrypto", "postgis", "pgstattuple", "snowball", "tsearch2", "uuid-ossp", "xml2","size"
-    ];
-    
-pub fn is_query_only(sql: String) -> bool { 
-    !is_blacklisted_query(&sql)
+    let mut cleaned = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut quote = None;
+    while let Some(c) = chars.next() {
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            cleaned.push(c);
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('-', Some('-')) | ('/', Some('/')) => {
+                while chars.next_if(|&c| c != '\n' && c != '\r').is_some() {}
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                // An unterminated block comment runs to the end of the query.
+                let mut star = false;
+                for c in chars.by_ref() {
+                    if star && c == '/' {
+                        break;
+                    }
+                    star = c == '*';
+                }
+            }
+            _ => {
+                if c == '\'' || c == '"' {
+                    quote = Some(c);
+                }
+                cleaned.push(c);
+            }
+        }
+    }
+    cleaned
 }
 
-fn is_blacklisted_query(sql: &str) -> bool {
-    const BLACKLIST_REGEX: &str = r###"(?i)\b(INSERT|UPDATE|DELETE|MERGE|UPSERT|TRUNCATE|RETURNING|OVERRIDING\s+SYSTEM\s+VALUE|CREATE|ALTER|DROP|RENAME|COMMENT|REINDEX|CLUSTER|DISCARD|BEGIN|COMMIT|ROLLBACK|SAVEPOINT|RELEASE|PREPARE|DEALLOCATE|GRANT|REVOKE|CREATE\s+USER|DROP\s+USER|CREATE\s+ROLE|DROP\s+ROLE|ALTER\s+USER|ALTER\s+ROLE|SET\s+ROLE|RESET\s+ROLE|SESSION\s+AUTHORIZATION|SET\s+SESSION\s+AUTHORIZATION|LOGIN|PASSWORD|CREATE\s+TABLE|DROP\s+TABLE|ALTER\s+TABLE|UNLOGGED|TEMP\s+TABLE|TEMPORARY|CREATE\s+SEQUENCE|ALTER\s+SEQUENCE|DROP\s+SEQUENCE|CREATE\s+VIEW|DROP\s+VIEW|ALTER\s+VIEW|MATERIALIZED|REFRESH\s+MATERIALIZED\s+VIEW|CREATE\s+FUNCTION|ALTER\s+FUNCTION|DROP\s+FUNCTION|CREATE\s+PROCEDURE|DROP\s+PROCEDURE|CALL|CREATE\s+TRIGGER|DROP\s+TRIGGER|CREATE\s+RULE|DROP\s+RULE|CREATE\s+INDEX|DROP\s+INDEX|USING\s+BTREE|USING\s+GIN|USING\s+HASH|CREATE\s+EXTENSION|ALTER\s+EXTENSION|DROP\s+EXTENSION|CREATE\s+SCHEMA|DROP\s+SCHEMA|ALTER\s+SCHEMA|COPY|DO|LISTEN|NOTIFY|UNLISTEN|EXPLAIN|ANALYZE|SHOW|SET|RESET|CONFIG|LOAD|VACUUM|CHECKPOINT|REASSIGN\s+OWNED|pg_sleep|pg_cancel_backend|pg_terminate_backend|pg_reload_conf|pg_rotate_logfile|pg_stat_reset|pg_logical_emit_message|pg_backend_pid|pg_postmaster_start_time|pg_current_xact_id|txid_current|pg_is_in_recovery|pg_last_xact_replay_timestamp|pg_switch_wal|pg_create_physical_replication_slot|pg_drop_replication_slot|pg_create_logical_replication_slot|pg_drop_logical_replication_slot|pg_current_wal_lsn|pg_wal_lsn_diff|pg_replication_origin|pg_create_restore_point|pg_start_backup|pg_stop_backup|pg_promote|pg_stat_|pg_replication_|pg_settings|pg_file_|pg_ls_|pg_log_|pg_read_file|pg_read_binary_file|pg_stat_file|pg_tablespace|pg_database|pg_user|pg_roles|pg_shadow|pg_authid|pg_auth_members|pg_group|pg_size_pretty|pg_table_size|pg_database_size|pg_indexes_size|pg_total_relation_size|pg_column_size|pg_relation_size|inet_client_addr|inet_client_port|inet_server_addr|inet_server_port|pg_hba_file_rules|pg_ident_file_mappings|--|/\*|\*/|#|;|;--|OR\s+1=1|' OR '1'='1|\" OR \"1\"=\"1|UNION\s+SELECT|INFORMATION_SCHEMA|SYSTEM_USER|CURRENT_CATALOG|CURRENT_SCHEMA|client_encoding|application_name|standard_conforming_strings|statement_timeout|idle_in_transaction_session_timeout|log_min_duration_statement|work_mem|maintenance_work_mem|shared_buffers|effective_cache_size|user|current_user|session_user|system_user|is_superuser|datestyle|timezone|plpgsql|pgcrypto|postgis|pgstattuple|snowball|tsearch2|uuid-ossp|xml2|size)\b"###;
-    let re = Regex::new(BLACKLIST_REGEX).unwrap();
-    re.is_match(sql)
+/// Collapses each run of whitespace to one space and trims the ends, leaving quoted literals
+/// as they are, so queries that differ only in layout compare equal.
+pub fn collapse_whitespace(query: &str) -> String {
+    let mut collapsed = String::with_capacity(query.len());
+    let mut quote = None;
+    let mut pending_space = false;
+    for c in query.chars() {
+        if quote.is_none() && c.is_whitespace() {
+            pending_space = !collapsed.is_empty();
+            continue;
+        }
+        if pending_space {
+            collapsed.push(' ');
+            pending_space = false;
+        }
+        match quote {
+            Some(q) if c == q => quote = None,
+            None if c == '\'' || c == '"' => quote = Some(c),
+            _ => {}
+        }
+        collapsed.push(c);
+    }
+    collapsed
 }
 
-pub fn is_sui_rpc_query(query: &str) -> bool {
-    let upper = query.to_uppercase();
-    ["SUI", "SUITEST", "SUIDEV"]
+/// Lowercase hex SHA-256 of `bytes`.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
         .iter()
-        .any(|target| upper.contains(target))
-}
-
-pub fn flatten_known_chain_tables(sql: &str) -> String {
-    let known_chains: HashSet<&'static str> = [
-        "sui", "suidev", "suitest", // Non-EVM
-        "eth", "sepolia", "arb", "base", "blast", "op", "poly", "mycelium", "mnt", "zks", "taiko",
-        "celo", "avax", "scroll", "bnb", "linea", "zora", "glmr", "movr", "ron", "ftm", "kava",
-        "gno", "mekong", "mina",
-    ]
-    .into_iter()
-    .collect();
-
-    let re = Regex::new(r"\b([a-zA-Z0-9_]+)\.([a-zA-Z0-9_]+)\b").unwrap();
-
-    re.replace_all(sql, |caps: &regex::Captures| {
-        let chain = &caps[1];
-        let table = &caps[2];
-        if known_chains.contains(chain) {
-            format!("{}_{}", chain, table)
-        } else {
-            caps[0].to_string() // Leave it untouched
-        }
-    })
-    .to_string()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
 }
 
 pub fn json_response<T: Serialize>(status: Status, data: T) -> status::Custom<RawJson<String>> {
@@ -156,11 +100,22 @@ pub fn json_error<E: ToString>(err: E) -> status::Custom<RawJson<String>> {
     )
 }
 
+/// A `504` naming the engine that ran out of time, distinct from the generic `500`.
+pub fn timeout_error(engine: &str, timeout: Duration) -> status::Custom<RawJson<String>> {
+    json_response(
+        Status::GatewayTimeout,
+        json!({
+            "error": format!("{} query exceeded the {} ms timeout", engine, timeout.as_millis()),
+            "code": "query_timeout",
+            "engine": engine,
+            "timeout_ms": timeout.as_millis() as u64,
+        }),
+    )
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::utils::is_query_only;
-
-    use super::remove_sql_comments;
+    use super::{collapse_whitespace, remove_sql_comments};
 
     #[test]
     fn test_remove_line_comments() {
@@ -194,52 +149,20 @@ mod tests {
     }
 
     #[test]
-    fn test_query_only_sql() {
-        let query = "SELECT * FROM users WHERE id = 1";
-        assert!(is_query_only(query.to_string()));
-    }
-
-    #[test]
-    fn test_insert_sql_is_not_query_only() {
-        let query = "INSERT INTO users (name) VALUES ('Alice')";
-        assert!(!is_query_only(query.to_string()));
-    }
-
-    #[test]
-    fn test_update_sql_is_not_query_only() {
-        let query = "UPDATE users SET name = 'Bob' WHERE id = 1";
-        assert!(!is_query_only(query.to_string()));
-    }
-
-    #[test]
-    fn test_dangerous_function_call_is_not_query_only() {
-        let query = "SELECT pg_sleep(10)";
-        assert!(!is_query_only(query.to_string()));
-    }
-
-    #[test]
-    fn test_safe_uppercase_select_query() {
-        let query = "SELECT name FROM USERS";
-        assert!(is_query_only(query.to_string()));
-    }
-
-    #[test]
-    fn test_sql_injection_pattern() {
-        let query = "' OR '1'='1";
-        assert!(!is_query_only(query.to_string()));
-    }
-
-    #[test]
-    fn test_union_select_attack() {
-        let query = "UNION SELECT password FROM users";
-        assert!(!is_query_only(query.to_string()));
+    fn test_comment_markers_in_literals() {
+        let sql = "SELECT * FROM t WHERE url = 'https://x' AND note = 'a--b' -- why\n\
+                   AND \"a/*b\" = '*/' /* done */";
+        let expected = "SELECT * FROM t WHERE url = 'https://x' AND note = 'a--b' \n\
+                        AND \"a/*b\" = '*/' ";
+        assert_eq!(remove_sql_comments(sql), expected);
+        assert_eq!(remove_sql_comments("SELECT 1 /* open"), "SELECT 1 ");
     }
 
     #[test]
-    fn test_with_comment_injection() {
-        let query = "SELECT * FROM users; -- drop table users;";
-        assert!(!is_query_only(query.to_string()));
+    fn test_collapse_whitespace() {
+        assert_eq!(
+            collapse_whitespace("  GET *\n\tFROM block 1   ON eth WHERE label = 'a  b' \n"),
+            "GET * FROM block 1 ON eth WHERE label = 'a  b'"
+        );
     }
-
 }
-